@@ -10,7 +10,7 @@ const genAI = new GoogleGenerativeAI(import.meta.env.VITE_GEMINI_API_KEY);
 const model = genAI.getGenerativeModel({
   model: "gemini-2.0-flash",
   systemInstruction:
-    "You are playing the role of a website summarizer. You will be given the raw content of an html page. Imagine that the html content was rendered and you were looking at the website that was generated. What information would be presented to you? What does the website say? Summarize the content of the website in three sentences. Do not make up information that is not included in the html content. Do not mention the structure of the html at all.",
+    "You are playing the role of a website summarizer. You will be given the title of a web page followed by passages of its text. What does the website say? Summarize the content of the website in three sentences. Do not make up information that is not included in the given text.",
 });
 
 export function SearchBar({
@@ -67,8 +67,17 @@ export function SearchResults({
             rel="noopener noreferrer"
             className="text-lg font-medium text-blue-500 hover:underline"
           >
-            {website.url}
+            {website.title}
           </a>
+          <p className="text-xs text-muted-foreground">{website.url}</p>
+          {website.snippets.map((snippet, index) => (
+            // the server escapes the text and only adds <mark> around query terms
+            <p
+              key={index}
+              className="text-sm"
+              dangerouslySetInnerHTML={{ __html: snippet }}
+            />
+          ))}
           {website.summary ? (
             <p className="text-sm">{website.summary}</p>
           ) : (
@@ -139,7 +148,10 @@ export default function App() {
     results.forEach((result: Result, index: number) => {
       setTimeout(
         () => {
-          model.generateContent(result.content.substring(0, 50000)).then(AIResponse => {
+          const text = [result.title, ...result.snippets]
+            .join("\n")
+            .replace(/<\/?mark>/g, "");
+          model.generateContent(text).then(AIResponse => {
             setWebsites((prevWebsites) => {
               const newWebsites: Website[] = [...prevWebsites];
               newWebsites[index] = {
@@ -1,10 +1,17 @@
 interface Result {
   url: string
-  content: string
+  title: string
+  // HTML-escaped passages with the query terms wrapped in <mark>
+  snippets: string[]
+  // query terms the page doesn't contain, for partial matches
+  missing_terms: string[]
+  // duplicates and same-host pages left out in favour of this one
+  folded: number
 }
 
 interface Website {
   url: string
-  content: string
+  title: string
+  snippets: string[]
   summary: string
 }
@@ -0,0 +1,775 @@
+use crate::inverted_index::join_ngram;
+use crate::query::Candidate;
+use crate::scorer::TermMatch;
+use crate::single_posting::{DocId, Posting};
+use crate::tokenizer::Tokenizer;
+use std::collections::HashMap;
+
+// Documents matching (part of) a query, with the query terms each one contains
+pub type Matches = HashMap<DocId, Vec<TermMatch>>;
+
+// Parsed query, bare terms next to each other are ANDed together
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryNode {
+    Term(String),
+    // terms that must appear next to each other in this order
+    Phrase(Vec<String>),
+    // terms that must all appear within a window spanning at most this many tokens, in any order
+    Near(Vec<String>, u32),
+    And(Vec<QueryNode>),
+    Or(Vec<QueryNode>),
+    Not(Box<QueryNode>),
+}
+
+// distance used by a bare NEAR without a /k
+const DEFAULT_NEAR_DISTANCE: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Word(String),
+    Phrase(String),
+    Near(u32),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+// Operators are only recognized in upper case so "or" and "not" can still be searched for
+fn lex(query: &str) -> Vec<Lexeme> {
+    let mut lexemes = Vec::new();
+    let mut chars = query.chars().peekable();
+    let mut at_word_start = true;
+    let mut word = String::new();
+
+    fn end_word(word: &mut String, lexemes: &mut Vec<Lexeme>) {
+        if word.is_empty() {
+            return;
+        }
+        lexemes.push(match word.as_str() {
+            "AND" => Lexeme::And,
+            "OR" => Lexeme::Or,
+            "NOT" => Lexeme::Not,
+            "NEAR" => Lexeme::Near(DEFAULT_NEAR_DISTANCE),
+            near if near.starts_with("NEAR/") && near[5..].parse::<u32>().is_ok() => {
+                Lexeme::Near(near[5..].parse().unwrap())
+            }
+            _ => Lexeme::Word(word.clone()),
+        });
+        word.clear();
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | ')' => {
+                end_word(&mut word, &mut lexemes);
+                lexemes.push(if c == '(' {
+                    Lexeme::Open
+                } else {
+                    Lexeme::Close
+                });
+                at_word_start = true;
+            }
+            // everything up to the closing quote (or the end of the query) is one phrase
+            '"' => {
+                end_word(&mut word, &mut lexemes);
+                let phrase: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                lexemes.push(Lexeme::Phrase(phrase));
+                at_word_start = true;
+            }
+            c if c.is_whitespace() => {
+                end_word(&mut word, &mut lexemes);
+                at_word_start = true;
+            }
+            // "-term" and "-(group)" negate, a dash inside a word is just a separator
+            '-' if at_word_start && chars.peek().is_some_and(|next| !next.is_whitespace()) => {
+                lexemes.push(Lexeme::Not);
+            }
+            c => {
+                word.push(c);
+                at_word_start = false;
+            }
+        }
+    }
+    end_word(&mut word, &mut lexemes);
+    lexemes
+}
+
+struct Parser<'a> {
+    lexemes: Vec<Lexeme>,
+    position: usize,
+    tokenizer: &'a Tokenizer,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.position)
+    }
+
+    // or_expr := and_expr (OR and_expr)*
+    fn parse_or(&mut self) -> Option<QueryNode> {
+        let mut children = Vec::new();
+        children.extend(self.parse_and());
+        while self.peek() == Some(&Lexeme::Or) {
+            self.position += 1;
+            children.extend(self.parse_and());
+        }
+        combine(children, QueryNode::Or)
+    }
+
+    // and_expr := unary (AND? unary)*
+    fn parse_and(&mut self) -> Option<QueryNode> {
+        let mut children = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Lexeme::Close) | Some(Lexeme::Or) => break,
+                Some(Lexeme::And) => self.position += 1,
+                _ => children.extend(self.parse_near()),
+            }
+        }
+        combine(children, QueryNode::And)
+    }
+
+    // near_expr := unary (NEAR/k unary)*
+    // A chain "a NEAR/2 b NEAR/3 c" needs all three terms inside a window of 2 + 3 tokens.
+    // NEAR only applies to single terms, any other operand turns the chain into a plain AND
+    fn parse_near(&mut self) -> Option<QueryNode> {
+        let mut operands = Vec::new();
+        operands.extend(self.parse_unary());
+        let mut distance = 0;
+        while let Some(&Lexeme::Near(k)) = self.peek() {
+            self.position += 1;
+            distance += k;
+            operands.extend(self.parse_unary());
+        }
+        if distance == 0 || operands.len() < 2 {
+            return combine(operands, QueryNode::And);
+        }
+        let terms: Option<Vec<String>> = operands
+            .iter()
+            .map(|operand| match operand {
+                QueryNode::Term(term) => Some(term.clone()),
+                _ => None,
+            })
+            .collect();
+        match terms {
+            Some(terms) => Some(QueryNode::Near(terms, distance)),
+            None => combine(operands, QueryNode::And),
+        }
+    }
+
+    // unary := (NOT | -) unary | ( or_expr ) | "phrase" | word
+    fn parse_unary(&mut self) -> Option<QueryNode> {
+        let lexeme = self.peek()?.clone();
+        self.position += 1;
+        match lexeme {
+            Lexeme::Not => self
+                .parse_unary()
+                .map(|node| QueryNode::Not(Box::new(node))),
+            Lexeme::Open => {
+                let node = self.parse_or();
+                // a missing closing parenthesis is forgiven
+                if self.peek() == Some(&Lexeme::Close) {
+                    self.position += 1;
+                }
+                node
+            }
+            // a word can tokenize into several terms ("e-mail") or none at all
+            Lexeme::Word(word) => combine(
+                self.tokenizer
+                    .tokenize(&word)
+                    .into_iter()
+                    .map(QueryNode::Term)
+                    .collect(),
+                QueryNode::And,
+            ),
+            Lexeme::Phrase(phrase) => {
+                let mut terms = self.tokenizer.tokenize(&phrase);
+                match terms.len() {
+                    0 => None,
+                    1 => terms.pop().map(QueryNode::Term),
+                    _ => Some(QueryNode::Phrase(terms)),
+                }
+            }
+            Lexeme::And | Lexeme::Or | Lexeme::Close | Lexeme::Near(_) => None,
+        }
+    }
+}
+
+fn combine(
+    mut children: Vec<QueryNode>,
+    operator: fn(Vec<QueryNode>) -> QueryNode,
+) -> Option<QueryNode> {
+    match children.len() {
+        0 => None,
+        1 => children.pop(),
+        _ => Some(operator(children)),
+    }
+}
+
+impl QueryNode {
+    // Lenient so a search box never errors: stray parentheses and dangling operators are skipped.
+    // Returns None when nothing searchable is left
+    pub fn parse(query: &str, tokenizer: &Tokenizer) -> Option<QueryNode> {
+        Self::parse_with_duplicates(query, tokenizer).map(QueryNode::dedup)
+    }
+
+    fn parse_with_duplicates(query: &str, tokenizer: &Tokenizer) -> Option<QueryNode> {
+        let mut parser = Parser {
+            lexemes: lex(query),
+            position: 0,
+            tokenizer,
+        };
+        let mut parts = Vec::new();
+        while parser.position < parser.lexemes.len() {
+            parts.extend(parser.parse_or());
+            // only a stray closing parenthesis can stop parse_or early
+            if parser.peek() == Some(&Lexeme::Close) {
+                parser.position += 1;
+            }
+        }
+        combine(parts, QueryNode::And)
+    }
+
+    // Identical siblings ("data data") are collapsed, their repetition is kept as query term frequency
+    fn dedup(self) -> QueryNode {
+        fn dedup_children(children: Vec<QueryNode>) -> Vec<QueryNode> {
+            let mut unique: Vec<QueryNode> = Vec::new();
+            for child in children.into_iter().map(QueryNode::dedup) {
+                if !unique.contains(&child) {
+                    unique.push(child);
+                }
+            }
+            unique
+        }
+        match self {
+            QueryNode::And(children) => combine(dedup_children(children), QueryNode::And).unwrap(),
+            QueryNode::Or(children) => combine(dedup_children(children), QueryNode::Or).unwrap(),
+            QueryNode::Not(child) => QueryNode::Not(Box::new(child.dedup())),
+            term => term,
+        }
+    }
+
+    // Word n-grams (2..=max_ngram) of the quoted phrases and of runs of plain terms written next to
+    // each other, as stored in the n-gram index. Negated parts and NEAR groups have none
+    pub fn ngrams(&self, max_ngram: usize) -> Vec<String> {
+        fn push_windows(terms: &[String], max_ngram: usize, ngrams: &mut Vec<String>) {
+            for n in 2..=max_ngram {
+                for window in terms.windows(n) {
+                    let ngram = join_ngram(window);
+                    if !ngrams.contains(&ngram) {
+                        ngrams.push(ngram);
+                    }
+                }
+            }
+        }
+        fn walk(node: &QueryNode, max_ngram: usize, ngrams: &mut Vec<String>) {
+            match node {
+                QueryNode::Phrase(terms) => push_windows(terms, max_ngram, ngrams),
+                QueryNode::And(children) => {
+                    let mut run = Vec::new();
+                    for child in children {
+                        if let QueryNode::Term(term) = child {
+                            run.push(term.clone());
+                            continue;
+                        }
+                        push_windows(&run, max_ngram, ngrams);
+                        run.clear();
+                        walk(child, max_ngram, ngrams);
+                    }
+                    push_windows(&run, max_ngram, ngrams);
+                }
+                QueryNode::Or(children) => children
+                    .iter()
+                    .for_each(|child| walk(child, max_ngram, ngrams)),
+                QueryNode::Term(_) | QueryNode::Near(..) | QueryNode::Not(_) => {}
+            }
+        }
+        let mut ngrams = Vec::new();
+        walk(self, max_ngram, &mut ngrams);
+        ngrams
+    }
+
+    // Only plain queries ("a b c") are relaxed by soft AND, explicit operators and phrases are kept strict
+    pub fn is_bare_conjunction(&self) -> bool {
+        match self {
+            QueryNode::Term(_) => true,
+            QueryNode::And(children) => children
+                .iter()
+                .all(|child| matches!(child, QueryNode::Term(_))),
+            _ => false,
+        }
+    }
+
+    // (terms, negated terms) of a conjunction of plain terms, some possibly negated, which can be
+    // intersected straight from the postings. None for anything else
+    pub fn conjunction_terms(&self) -> Option<(Vec<&String>, Vec<&String>)> {
+        let QueryNode::And(children) = self else {
+            return None;
+        };
+        let mut terms = Vec::new();
+        let mut negated = Vec::new();
+        for child in children {
+            match child {
+                QueryNode::Term(term) => terms.push(term),
+                QueryNode::Not(inner) => match inner.as_ref() {
+                    QueryNode::Term(term) => negated.push(term),
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+        (!terms.is_empty()).then_some((terms, negated))
+    }
+
+    // terms of a single term or of plain terms ORed together, whose best matches can be found
+    // without scoring every document holding one of them. None for anything else
+    pub fn disjunction_terms(&self) -> Option<Vec<&String>> {
+        match self {
+            QueryNode::Term(term) => Some(vec![term]),
+            QueryNode::Or(children) => children
+                .iter()
+                .map(|child| match child {
+                    QueryNode::Term(term) => Some(term),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+
+    // Every term in the query with how often it appears un-negated, negated terms count as 0
+    pub fn term_frequencies(query: &str, tokenizer: &Tokenizer) -> Vec<(String, u32)> {
+        fn walk(node: &QueryNode, negated: bool, frequencies: &mut Vec<(String, u32)>) {
+            let mut count_term = |term: &String| {
+                let increment = if negated { 0 } else { 1 };
+                match frequencies.iter_mut().find(|(t, _)| t == term) {
+                    Some((_, count)) => *count += increment,
+                    None => frequencies.push((term.clone(), increment)),
+                }
+            };
+            match node {
+                QueryNode::Term(term) => count_term(term),
+                QueryNode::Phrase(terms) | QueryNode::Near(terms, _) => {
+                    terms.iter().for_each(count_term)
+                }
+                QueryNode::And(children) | QueryNode::Or(children) => {
+                    for child in children {
+                        walk(child, negated, frequencies);
+                    }
+                }
+                QueryNode::Not(child) => walk(child, !negated, frequencies),
+            }
+        }
+        // counted before dedup so repeated terms keep their weight
+        let mut frequencies = Vec::new();
+        if let Some(node) = Self::parse_with_duplicates(query, tokenizer) {
+            walk(&node, false, &mut frequencies);
+        }
+        frequencies
+    }
+
+    // Evaluates the tree over the fetched postings, keyed by term.
+    // A negation on its own is taken against every document in the index
+    pub fn evaluate(
+        &self,
+        candidates: &HashMap<String, Candidate>,
+        document_count: DocId,
+    ) -> Matches {
+        match self {
+            QueryNode::Term(term) => candidates
+                .get(term)
+                .map(|candidate| {
+                    candidate
+                        .doc_ids
+                        .iter()
+                        .map(|(&doc_id, posting)| (doc_id, vec![candidate.term_match(posting)]))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            QueryNode::Phrase(terms) => positional_matches(terms, candidates, contains_phrase),
+            QueryNode::Near(terms, distance) => positional_matches(terms, candidates, |postings| {
+                let positions: Vec<&[u32]> = postings
+                    .iter()
+                    .map(|posting| posting.positions.as_slice())
+                    .collect();
+                minimal_window(&positions).is_some_and(|window| window <= *distance)
+            }),
+            QueryNode::And(children) => {
+                let (negated, positive): (Vec<&QueryNode>, Vec<&QueryNode>) = children
+                    .iter()
+                    .partition(|child| matches!(child, QueryNode::Not(_)));
+                let mut results: Vec<Matches> = positive
+                    .iter()
+                    .map(|child| child.evaluate(candidates, document_count))
+                    .collect();
+                // intersect starting from the smallest list
+                results.sort_by_key(|result| result.len());
+                let mut results = results.into_iter();
+                let mut matches = match results.next() {
+                    Some(smallest) => smallest,
+                    None => all_documents(document_count),
+                };
+                for mut result in results {
+                    matches.retain(|doc_id, terms| match result.remove(doc_id) {
+                        Some(other_terms) => {
+                            terms.extend(other_terms);
+                            true
+                        }
+                        None => false,
+                    });
+                }
+                for child in negated {
+                    if let QueryNode::Not(inner) = child {
+                        let excluded = inner.evaluate(candidates, document_count);
+                        matches.retain(|doc_id, _| !excluded.contains_key(doc_id));
+                    }
+                }
+                matches
+            }
+            QueryNode::Or(children) => {
+                let mut matches = Matches::new();
+                for child in children {
+                    for (doc_id, terms) in child.evaluate(candidates, document_count) {
+                        matches.entry(doc_id).or_default().extend(terms);
+                    }
+                }
+                matches
+            }
+            QueryNode::Not(child) => {
+                let excluded = child.evaluate(candidates, document_count);
+                let mut matches = all_documents(document_count);
+                matches.retain(|doc_id, _| !excluded.contains_key(doc_id));
+                matches
+            }
+        }
+    }
+}
+
+// Documents containing every term whose postings pass the positional check
+fn positional_matches(
+    terms: &[String],
+    candidates: &HashMap<String, Candidate>,
+    check: impl Fn(&[&Posting]) -> bool,
+) -> Matches {
+    let term_candidates: Option<Vec<&Candidate>> =
+        terms.iter().map(|term| candidates.get(term)).collect();
+    let Some(term_candidates) = term_candidates else {
+        return Matches::new();
+    };
+    let mut matches = Matches::new();
+    // walk the rarest term's documents, keeping the query order for the position check
+    let rarest = term_candidates
+        .iter()
+        .min_by_key(|candidate| candidate.doc_ids.len())
+        .unwrap();
+    for &doc_id in rarest.doc_ids.keys() {
+        let postings: Option<Vec<&Posting>> = term_candidates
+            .iter()
+            .map(|candidate| candidate.doc_ids.get(&doc_id))
+            .collect();
+        if let Some(postings) = postings {
+            if check(&postings) {
+                let terms = term_candidates
+                    .iter()
+                    .zip(&postings)
+                    .map(|(candidate, posting)| candidate.term_match(posting))
+                    .collect();
+                matches.insert(doc_id, terms);
+            }
+        }
+    }
+    matches
+}
+
+// Span (last - first position) of the smallest window holding at least one position from every
+// list, None when a list is empty. Each list must be sorted
+pub fn minimal_window(positions: &[&[u32]]) -> Option<u32> {
+    if positions.iter().any(|list| list.is_empty()) {
+        return None;
+    }
+    // merge every (position, list) pair and slide a window over them
+    let mut merged: Vec<(u32, usize)> = positions
+        .iter()
+        .enumerate()
+        .flat_map(|(list, list_positions)| list_positions.iter().map(move |&p| (p, list)))
+        .collect();
+    merged.sort_unstable();
+    let mut counts = vec![0; positions.len()];
+    let mut covered = 0;
+    let mut best: Option<u32> = None;
+    let mut start = 0;
+    for end in 0..merged.len() {
+        let list = merged[end].1;
+        counts[list] += 1;
+        if counts[list] == 1 {
+            covered += 1;
+        }
+        while covered == positions.len() {
+            let span = merged[end].0 - merged[start].0;
+            best = Some(best.map_or(span, |best| best.min(span)));
+            let first = merged[start].1;
+            counts[first] -= 1;
+            if counts[first] == 0 {
+                covered -= 1;
+            }
+            start += 1;
+        }
+    }
+    best
+}
+
+// true when some position of the first term is followed by each next term one token later
+fn contains_phrase(postings: &[&Posting]) -> bool {
+    postings[0].positions.iter().any(|&start| {
+        postings
+            .iter()
+            .enumerate()
+            .skip(1)
+            .all(|(offset, posting)| {
+                posting
+                    .positions
+                    .binary_search(&(start + offset as u32))
+                    .is_ok()
+            })
+    })
+}
+
+fn all_documents(document_count: DocId) -> Matches {
+    (1..=document_count)
+        .map(|doc_id| (doc_id, Vec::new()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> Option<QueryNode> {
+        QueryNode::parse(query, &Tokenizer::new())
+    }
+
+    fn term(term: &str) -> QueryNode {
+        QueryNode::Term(term.to_string())
+    }
+
+    fn terms(terms: &[&str]) -> Vec<String> {
+        terms.iter().map(|term| term.to_string()).collect()
+    }
+
+    // candidates holding the given (term, doc id, positions)
+    fn candidates(postings: &[(&str, DocId, &[u32])]) -> HashMap<String, Candidate> {
+        let mut candidates: HashMap<String, Candidate> = HashMap::new();
+        for &(term, doc_id, positions) in postings {
+            candidates
+                .entry(term.to_string())
+                .or_insert_with(|| Candidate::new(term.to_string(), 1, 0))
+                .insert(Posting::new(
+                    doc_id,
+                    positions.len() as u32,
+                    positions.to_vec(),
+                ));
+        }
+        candidates
+    }
+
+    fn doc_ids(matches: &Matches) -> Vec<DocId> {
+        let mut doc_ids: Vec<DocId> = matches.keys().copied().collect();
+        doc_ids.sort_unstable();
+        doc_ids
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expected = QueryNode::Or(vec![
+            QueryNode::And(vec![term("cat"), term("dog")]),
+            term("fish"),
+        ]);
+        assert_eq!(parse("cat dog OR fish"), Some(expected.clone()));
+        assert_eq!(parse("cat AND dog OR fish"), Some(expected));
+        assert_eq!(
+            parse("cat OR dog fish"),
+            Some(QueryNode::Or(vec![
+                term("cat"),
+                QueryNode::And(vec![term("dog"), term("fish")]),
+            ]))
+        );
+        assert_eq!(
+            parse("cat (dog OR fish)"),
+            Some(QueryNode::And(vec![
+                term("cat"),
+                QueryNode::Or(vec![term("dog"), term("fish")]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn operators_are_only_upper_case() {
+        assert_eq!(
+            parse("cat or dog"),
+            Some(QueryNode::And(vec![term("cat"), term("or"), term("dog")]))
+        );
+    }
+
+    #[test]
+    fn unbalanced_parentheses_are_forgiven() {
+        let expected = Some(QueryNode::Or(vec![term("cat"), term("dog")]));
+        assert_eq!(parse("(cat OR dog"), expected);
+        assert_eq!(parse("cat OR dog)"), expected);
+        assert_eq!(
+            parse("cat) dog"),
+            Some(QueryNode::And(vec![term("cat"), term("dog")]))
+        );
+        assert_eq!(parse("()"), None);
+    }
+
+    #[test]
+    fn dangling_operators_are_skipped() {
+        assert_eq!(parse("cat OR"), Some(term("cat")));
+        assert_eq!(parse("cat AND"), Some(term("cat")));
+        assert_eq!(parse("cat NOT"), Some(term("cat")));
+        assert_eq!(parse("OR cat"), Some(term("cat")));
+        assert_eq!(parse("cat NEAR"), Some(term("cat")));
+        assert_eq!(parse("AND OR NOT"), None);
+    }
+
+    #[test]
+    fn a_lone_negation_excludes_from_every_document() {
+        let node = parse("-cat").unwrap();
+        assert_eq!(node, QueryNode::Not(Box::new(term("cat"))));
+        let matches = node.evaluate(&candidates(&[("cat", 2, &[0])]), 4);
+        assert_eq!(doc_ids(&matches), vec![1, 3, 4]);
+        // a dash inside a word or before a space doesn't negate
+        assert_eq!(
+            parse("cat-dog"),
+            Some(QueryNode::And(vec![term("cat"), term("dog")]))
+        );
+        assert_eq!(
+            parse("cat - dog"),
+            Some(QueryNode::And(vec![term("cat"), term("dog")]))
+        );
+    }
+
+    #[test]
+    fn negated_terms_of_a_conjunction_are_set_apart() {
+        let node = parse("cat NOT dog fish").unwrap();
+        let (positive, negated) = node.conjunction_terms().unwrap();
+        assert_eq!(positive, vec!["cat", "fish"]);
+        assert_eq!(negated, vec!["dog"]);
+        let matches = node.evaluate(
+            &candidates(&[
+                ("cat", 1, &[0]),
+                ("cat", 2, &[0]),
+                ("fish", 1, &[1]),
+                ("fish", 2, &[1]),
+                ("dog", 2, &[2]),
+            ]),
+            2,
+        );
+        assert_eq!(doc_ids(&matches), vec![1]);
+    }
+
+    #[test]
+    fn near_chains_add_up_their_distances() {
+        assert_eq!(
+            parse("cat NEAR/2 dog NEAR/3 fish"),
+            Some(QueryNode::Near(terms(&["cat", "dog", "fish"]), 5))
+        );
+        assert_eq!(
+            parse("cat NEAR dog"),
+            Some(QueryNode::Near(
+                terms(&["cat", "dog"]),
+                DEFAULT_NEAR_DISTANCE
+            ))
+        );
+        // NEAR of anything but single terms is a plain AND
+        assert_eq!(
+            parse("cat NEAR/2 (dog OR fish)"),
+            Some(QueryNode::And(vec![
+                term("cat"),
+                QueryNode::Or(vec![term("dog"), term("fish")]),
+            ]))
+        );
+        let node = parse("cat NEAR/2 dog NEAR/3 fish").unwrap();
+        let matches = node.evaluate(
+            &candidates(&[
+                ("cat", 1, &[10]),
+                ("dog", 1, &[12]),
+                ("fish", 1, &[15]),
+                ("cat", 2, &[10]),
+                ("dog", 2, &[12]),
+                ("fish", 2, &[16]),
+            ]),
+            2,
+        );
+        assert_eq!(doc_ids(&matches), vec![1]);
+    }
+
+    #[test]
+    fn phrases_are_parsed_from_quotes() {
+        assert_eq!(
+            parse("\"cat dog\" fish"),
+            Some(QueryNode::And(vec![
+                QueryNode::Phrase(terms(&["cat", "dog"])),
+                term("fish"),
+            ]))
+        );
+        // a missing closing quote runs to the end, a one word phrase is a term
+        assert_eq!(
+            parse("fish \"cat dog"),
+            Some(QueryNode::And(vec![
+                term("fish"),
+                QueryNode::Phrase(terms(&["cat", "dog"])),
+            ]))
+        );
+        assert_eq!(parse("\"cat\""), Some(term("cat")));
+        assert_eq!(parse("\"\""), None);
+    }
+
+    #[test]
+    fn phrases_need_the_terms_in_order_and_adjacent() {
+        let node = parse("\"cat dog fish\"").unwrap();
+        let matches = node.evaluate(
+            &candidates(&[
+                ("cat", 1, &[3, 20]),
+                ("dog", 1, &[4, 21]),
+                ("fish", 1, &[22]),
+                ("cat", 2, &[3]),
+                ("dog", 2, &[2]),
+                ("fish", 2, &[4]),
+            ]),
+            2,
+        );
+        assert_eq!(doc_ids(&matches), vec![1]);
+    }
+
+    #[test]
+    fn contains_phrase_checks_consecutive_positions() {
+        let cat = Posting::new(1, 2, vec![1, 7]);
+        let dog = Posting::new(1, 1, vec![8]);
+        let fish = Posting::new(1, 1, vec![3]);
+        assert!(contains_phrase(&[&cat, &dog]));
+        assert!(!contains_phrase(&[&dog, &cat]));
+        assert!(!contains_phrase(&[&cat, &fish]));
+        assert!(!contains_phrase(&[&cat, &Posting::new(1, 0, Vec::new())]));
+    }
+
+    #[test]
+    fn minimal_window_spans_one_position_of_every_list() {
+        assert_eq!(minimal_window(&[&[1, 10], &[4], &[5, 12]]), Some(4));
+        assert_eq!(minimal_window(&[&[1, 10], &[11]]), Some(1));
+        assert_eq!(minimal_window(&[&[7]]), Some(0));
+        assert_eq!(minimal_window(&[&[3], &[3]]), Some(0));
+        assert_eq!(minimal_window(&[&[1, 2], &[]]), None);
+    }
+
+    #[test]
+    fn repeated_terms_collapse_but_keep_their_count() {
+        assert_eq!(parse("cat cat"), Some(term("cat")));
+        assert_eq!(
+            QueryNode::term_frequencies("cat dog cat -dog", &Tokenizer::new()),
+            vec![("cat".to_string(), 2), ("dog".to_string(), 1)]
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use crate::postings::PostingsCodec;
+use crate::scorer::{Bm25Params, FieldWeights, Ranking, WeightedRanking};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+// file names inside the index directory
+const IDBOOK_FILE: &str = "id_book.txt";
+const MERGED_INDEX_DIR: &str = "merged";
+const FORMAT_VERSION_FILE: &str = "format_version.txt";
+const METADATA_FILE: &str = "metadata.json";
+const DOCUMENT_LENGTHS_FILE: &str = "doc_lengths.bin";
+const DOCUMENT_NORMS_FILE: &str = "doc_norms.bin";
+// written by the page_rank binary
+const PAGE_RANK_FILE: &str = "page_rank.bin";
+const LINK_GRAPH_FILE: &str = "link_graph.bin";
+const DUPLICATE_CLUSTERS_FILE: &str = "duplicate_clusters.bin";
+// compressed title, text and metadata of every document, so serving doesn't need the crawl
+const DOCUMENT_STORE_FILE: &str = "documents.bin";
+const DOCUMENT_OFFSETS_FILE: &str = "document_offsets.bin";
+// bigram and trigram partitions live in this subdirectory of every batch and of the merged index
+pub const NGRAM_DIR: &str = "ngrams";
+// anchor text of links between crawled pages, indexed after every page has its doc id
+const ANCHOR_DIR: &str = "anchors";
+
+// Bump whenever the on-disk layout changes so an old index is rejected instead of misread.
+// Indexes from before the marker existed (u16 doc ids) have no version file at all
+// 2: u32 doc ids and term frequencies
+// 3: metadata.json with corpus statistics
+// 4: doc_lengths.bin with per-document token lengths
+// 5: doc_norms.bin with per-document tf-idf vector lengths
+// 6: postings carry delta-encoded token positions
+// 7: postings carry per-field term frequencies, important text is no longer repeated
+// 8: duplicate_clusters.bin with the near-duplicate cluster of each document
+// 9: documents.bin and document_offsets.bin store the extracted text of each document
+// 10: merged postings may be binary, metadata.json records their codec
+// 11: binary postings start with skip pointers
+// 12: binary postings carry score bounds per term and per block
+// 13: blocks of the block codec store the length of each posting's details
+// 14: score bounds carry the shortest document length
+pub const INDEX_FORMAT_VERSION: u32 = 14;
+
+// environment variables that override whatever the config file says
+pub const CONFIG_ENV: &str = "SEARCH_ENGINE_CONFIG";
+const CORPUS_DIR_ENV: &str = "SEARCH_ENGINE_CORPUS_DIR";
+const INDEX_DIR_ENV: &str = "SEARCH_ENGINE_INDEX_DIR";
+const BATCH_SIZE_ENV: &str = "SEARCH_ENGINE_BATCH_SIZE";
+const HOST_ENV: &str = "SEARCH_ENGINE_HOST";
+const PORT_ENV: &str = "SEARCH_ENGINE_PORT";
+
+// Where the corpus and index live and how the server is exposed.
+// Precedence is defaults < config file (TOML or JSON) < environment variables < CLI flags
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexConfig {
+    pub corpus_dir: PathBuf,
+    pub index_dir: PathBuf,
+    pub batch_size: u16,
+    pub host: String,
+    pub port: u16,
+    pub bm25: Bm25Params,
+    // applied at query time, the merger also uses them for the cosine document norms
+    pub field_weights: FieldWeights,
+    // default ranking when a request doesn't pick one
+    pub ranking: Ranking,
+    // components used by Ranking::Linear
+    pub linear: Vec<WeightedRanking>,
+    // when fewer documents than this match every term, partial matches are added, 0 disables it
+    pub soft_and_min_results: usize,
+    // how much a document whose query terms are adjacent is boosted, 0 disables proximity scoring
+    pub proximity_weight: f64,
+    // longest word n-gram indexed next to the unigrams (2 = bigrams, 3 = bigrams and trigrams),
+    // 1 disables the n-gram index
+    pub max_ngram: usize,
+    // how much a document containing every n-gram of the query's phrases is boosted, 0 disables it
+    pub phrase_weight: f64,
+    // how much PageRank (from the page_rank binary) lifts a document's score, 0 disables it
+    pub page_rank_weight: f64,
+    // re-rank the best results by their HITS authority score, a request can turn it on for itself
+    pub hits: bool,
+    // how many of the best results form the HITS root set
+    pub hits_root_size: usize,
+    // how much the best authority in the root set lifts its score
+    pub hits_weight: f64,
+    // show only the best page of each near-duplicate cluster
+    pub collapse_duplicates: bool,
+    // results shown per host, the rest are folded into the best one, 0 disables the limit
+    pub max_results_per_host: usize,
+    // how the merger writes the merged postings, `convert` changes it for an existing index
+    pub postings_codec: PostingsCodec,
+    // rank single terms and OR queries with Block-Max WAND, which skips the documents that can't
+    // make the best results instead of scoring every match
+    pub dynamic_pruning: bool,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            corpus_dir: PathBuf::from("../developer/DEV/"),
+            index_dir: PathBuf::from("inverted_index"),
+            batch_size: 5000,
+            host: String::from("127.0.0.1"),
+            port: 3000,
+            bm25: Bm25Params::default(),
+            field_weights: FieldWeights::default(),
+            ranking: Ranking::default(),
+            linear: vec![
+                WeightedRanking {
+                    ranking: Ranking::Bm25,
+                    weight: 0.7,
+                },
+                WeightedRanking {
+                    ranking: Ranking::Cosine,
+                    weight: 0.3,
+                },
+            ],
+            soft_and_min_results: 10,
+            proximity_weight: 0.5,
+            max_ngram: 1,
+            phrase_weight: 0.5,
+            page_rank_weight: 0.2,
+            hits: false,
+            hits_root_size: 50,
+            hits_weight: 1.0,
+            collapse_duplicates: true,
+            max_results_per_host: 0,
+            postings_codec: PostingsCodec::default(),
+            dynamic_pruning: true,
+        }
+    }
+}
+
+impl IndexConfig {
+    // Loads the config file (if any, falling back to $SEARCH_ENGINE_CONFIG) and applies env overrides
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os(CONFIG_ENV).map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        config.apply_env_overrides()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Settings the builder can't work with, checked again after the command line overrides them
+    pub fn validate(&self) -> io::Result<()> {
+        if self.batch_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "batch_size must be at least 1",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut config: IndexConfig = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            _ => toml::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        // relative paths in a config file are relative to the file, not to wherever we are run from
+        if let Some(base) = path.parent() {
+            config.corpus_dir = base.join(&config.corpus_dir);
+            config.index_dir = base.join(&config.index_dir);
+        }
+        Ok(config)
+    }
+
+    fn apply_env_overrides(&mut self) -> io::Result<()> {
+        if let Some(corpus_dir) = env::var_os(CORPUS_DIR_ENV) {
+            self.corpus_dir = PathBuf::from(corpus_dir);
+        }
+        if let Some(index_dir) = env::var_os(INDEX_DIR_ENV) {
+            self.index_dir = PathBuf::from(index_dir);
+        }
+        if let Ok(batch_size) = env::var(BATCH_SIZE_ENV) {
+            self.batch_size = parse_env(BATCH_SIZE_ENV, &batch_size)?;
+        }
+        if let Ok(host) = env::var(HOST_ENV) {
+            self.host = host;
+        }
+        if let Ok(port) = env::var(PORT_ENV) {
+            self.port = parse_env(PORT_ENV, &port)?;
+        }
+        Ok(())
+    }
+
+    pub fn id_book_path(&self) -> PathBuf {
+        self.index_dir.join(IDBOOK_FILE)
+    }
+
+    pub fn document_lengths_path(&self) -> PathBuf {
+        self.index_dir.join(DOCUMENT_LENGTHS_FILE)
+    }
+
+    pub fn document_norms_path(&self) -> PathBuf {
+        self.index_dir.join(DOCUMENT_NORMS_FILE)
+    }
+
+    pub fn page_rank_path(&self) -> PathBuf {
+        self.index_dir.join(PAGE_RANK_FILE)
+    }
+
+    pub fn duplicate_clusters_path(&self) -> PathBuf {
+        self.index_dir.join(DUPLICATE_CLUSTERS_FILE)
+    }
+
+    pub fn document_store_path(&self) -> PathBuf {
+        self.index_dir.join(DOCUMENT_STORE_FILE)
+    }
+
+    pub fn document_offsets_path(&self) -> PathBuf {
+        self.index_dir.join(DOCUMENT_OFFSETS_FILE)
+    }
+
+    pub fn link_graph_path(&self) -> PathBuf {
+        self.index_dir.join(LINK_GRAPH_FILE)
+    }
+
+    pub fn metadata_path(&self) -> PathBuf {
+        self.index_dir.join(METADATA_FILE)
+    }
+
+    pub fn merged_dir(&self) -> PathBuf {
+        self.index_dir.join(MERGED_INDEX_DIR)
+    }
+
+    pub fn merged_ngram_dir(&self) -> PathBuf {
+        self.merged_dir().join(NGRAM_DIR)
+    }
+
+    // partial index written for the given batch during the build
+    pub fn batch_dir(&self, batch: usize) -> PathBuf {
+        self.index_dir.join(batch.to_string())
+    }
+
+    // partial index holding only the anchor text field, merged like one more batch
+    pub fn anchor_dir(&self) -> PathBuf {
+        self.index_dir.join(ANCHOR_DIR)
+    }
+
+    pub fn batch_ngram_dir(&self, batch: usize) -> PathBuf {
+        self.batch_dir(batch).join(NGRAM_DIR)
+    }
+
+    pub fn write_format_version(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.index_dir)?;
+        fs::write(
+            self.index_dir.join(FORMAT_VERSION_FILE),
+            INDEX_FORMAT_VERSION.to_string(),
+        )
+    }
+
+    pub fn check_format_version(&self) -> io::Result<()> {
+        let path = self.index_dir.join(FORMAT_VERSION_FILE);
+        let version = match fs::read_to_string(&path) {
+            Ok(contents) => contents.trim().parse::<u32>().ok(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        match version {
+            Some(INDEX_FORMAT_VERSION) => Ok(()),
+            Some(version) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "index at {} has format version {}, expected {}; rebuild it",
+                    self.index_dir.display(),
+                    version,
+                    INDEX_FORMAT_VERSION
+                ),
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "index at {} has no readable {}, it predates format version {}; rebuild it",
+                    self.index_dir.display(),
+                    FORMAT_VERSION_FILE,
+                    INDEX_FORMAT_VERSION
+                ),
+            )),
+        }
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str, value: &str) -> io::Result<T> {
+    value.trim().parse::<T>().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has an invalid value: {}", name, value),
+        )
+    })
+}
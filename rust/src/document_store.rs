@@ -0,0 +1,121 @@
+use crate::config::IndexConfig;
+use crate::single_posting::DocId;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+
+// bytes per entry in the offset table: u64 offset and u32 length of the record
+const OFFSET_ENTRY_SIZE: usize = 12;
+
+// Everything the serving side needs to show a result, so it never touches the crawl
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StoredDocument {
+    pub url: String,
+    pub title: Option<String>,
+    // extracted body text, what snippets are cut from
+    pub text: String,
+    // tokens indexed from the body
+    pub length: u32,
+    // fetch metadata from the crawl
+    pub encoding: String,
+    pub content_bytes: u64,
+    // crawl file the document was read from
+    pub source_path: String,
+}
+
+// Appends one deflate-compressed JSON record per document to the store and writes the offset
+// table once every document is in. Records may arrive in any doc id order
+pub struct DocumentStoreWriter {
+    file: BufWriter<File>,
+    position: u64,
+    // (offset, length) indexed by doc id - 1, length 0 for ids never added
+    entries: Vec<(u64, u32)>,
+}
+
+impl DocumentStoreWriter {
+    pub fn create(config: &IndexConfig) -> io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(config.document_store_path())?),
+            position: 0,
+            entries: Vec::new(),
+        })
+    }
+
+    pub fn add(&mut self, id: DocId, document: &StoredDocument) -> io::Result<()> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        serde_json::to_writer(&mut encoder, document)?;
+        let record = encoder.finish()?;
+        self.file.write_all(&record)?;
+        let index = id as usize - 1;
+        if self.entries.len() <= index {
+            self.entries.resize(index + 1, (0, 0));
+        }
+        self.entries[index] = (self.position, record.len() as u32);
+        self.position += record.len() as u64;
+        Ok(())
+    }
+
+    // Little-endian (u64 offset, u32 length) per doc id, doc 1 first, padded to document_count
+    pub fn finish(mut self, config: &IndexConfig, document_count: DocId) -> io::Result<()> {
+        self.file.flush()?;
+        self.entries.resize(document_count as usize, (0, 0));
+        let bytes: Vec<u8> = self
+            .entries
+            .iter()
+            .flat_map(|&(offset, length)| {
+                offset.to_le_bytes().into_iter().chain(length.to_le_bytes())
+            })
+            .collect();
+        std::fs::write(config.document_offsets_path(), bytes)
+    }
+}
+
+// Random access to the stored documents. The offset table is loaded whole at startup, the
+// records are read from disk on demand
+pub struct DocumentStore {
+    file: File,
+    entries: Vec<(u64, u32)>,
+}
+
+impl DocumentStore {
+    pub fn load(config: &IndexConfig) -> io::Result<Self> {
+        let bytes = std::fs::read(config.document_offsets_path())?;
+        let entries = bytes
+            .chunks_exact(OFFSET_ENTRY_SIZE)
+            .map(|chunk| {
+                let (offset, length) = chunk.split_at(8);
+                (
+                    u64::from_le_bytes(offset.try_into().unwrap()),
+                    u32::from_le_bytes(length.try_into().unwrap()),
+                )
+            })
+            .collect();
+        Ok(Self {
+            file: File::open(config.document_store_path())?,
+            entries,
+        })
+    }
+
+    pub fn get(&self, id: DocId) -> io::Result<StoredDocument> {
+        let (offset, length) = match self.entries.get((id as usize).wrapping_sub(1)) {
+            Some(&(offset, length)) if length > 0 => (offset, length),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("document {} is not in the document store", id),
+                ))
+            }
+        };
+        let mut record = vec![0; length as usize];
+        // &File implements Read and Seek, so lookups don't need &mut self
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut record)?;
+        let mut json = Vec::new();
+        DeflateDecoder::new(record.as_slice()).read_to_end(&mut json)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
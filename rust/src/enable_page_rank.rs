@@ -1,63 +1,175 @@
-// ! Not a file in use, mainly for testing, debugging and looking at statistics
-use std::fs::{self, File};
-use std::os::windows::fs::MetadataExt;
-use std::sync::{Arc, Mutex};
+// Computes PageRank over the links between crawled pages and stores one score per document
+// next to the index, along with the link graph itself. Run it after `search_engine build`.
+
+use clap::Parser;
+use search_engine::config::IndexConfig;
+use search_engine::id_book::{normalize_url, resolve_link, DocumentWeights, IDBookElement};
+use search_engine::index_metadata::IndexMetadata;
+use search_engine::link_graph::LinkGraph;
+use search_engine::single_posting::DocId;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread;
-pub fn main() {
-    let twty = Arc::new(Mutex::new(Vec::new()));
-    let five = Arc::new(Mutex::new(Vec::new()));
-    let files = get_files_in_dir("../developer/DEV");
+use url::Url;
 
-    let chunk_size = 5000;
-    let mut handles = vec![];
+#[derive(Parser)]
+#[command(
+    name = "page_rank",
+    about = "Compute PageRank over the crawled link graph"
+)]
+struct Cli {
+    /// TOML or JSON config file, defaults to $SEARCH_ENGINE_CONFIG
+    #[arg(long)]
+    config: Option<PathBuf>,
+    #[arg(long)]
+    index_dir: Option<PathBuf>,
+    /// probability of following a link instead of jumping to a random page
+    #[arg(long, default_value_t = 0.85)]
+    damping: f64,
+    #[arg(long, default_value_t = 100)]
+    max_iterations: usize,
+    /// stop once the scores move less than this in total (L1) between iterations
+    #[arg(long, default_value_t = 1e-8)]
+    tolerance: f64,
+}
 
-    for chunk in files.chunks(chunk_size) {
-        let chunk_files = chunk.to_vec();
-        let twty_clone = Arc::clone(&twty);
-        let five_clone = Arc::clone(&five);
-
-        let handle: thread::JoinHandle<()> = thread::spawn(move || {
-            for file in chunk_files {
-                match File::open(&file) {
-                    Ok(file_string) => {
-                        if let Ok(metadata) = file_string.metadata() {
-                            let size = metadata.file_size();
-                            if size > 20000000 {
-                                twty_clone.lock().unwrap().push(file);
-                            } else if size > 5000000 {
-                                five_clone.lock().unwrap().push(file);
-                            }
-                        }
-                    }
-                    Err(_) => continue,
-                }
-            }
-        });
+#[derive(Deserialize)]
+struct Page {
+    url: String,
+    content: String,
+}
+
+pub fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let mut config = IndexConfig::load(cli.config.as_deref())?;
+    if let Some(index_dir) = cli.index_dir {
+        config.index_dir = index_dir;
+    }
+    config.check_format_version()?;
+    // ties the scores to the doc ids of this build
+    let stamp = IndexMetadata::load(&config)?.stamp();
+    let documents = IDBookElement::read_all(&config)?;
+    if documents.is_empty() {
+        println!("No documents found in {}", config.index_dir.display());
+        return Ok(());
+    }
+
+    let links = build_link_graph(documents);
+    let edge_count: usize = links.iter().map(Vec::len).sum();
+    println!("Link graph: {} pages, {} links", links.len(), edge_count);
+
+    // kept for query-time link analysis (HITS)
+    LinkGraph::new(&links).save(&config.link_graph_path(), stamp)?;
 
-        handles.push(handle);
+    let scores = page_rank(&links, cli.damping, cli.max_iterations, cli.tolerance);
+    DocumentWeights::new(scores).save_stamped(&config.page_rank_path(), stamp)?;
+    println!("Wrote PageRank to {}", config.page_rank_path().display());
+    Ok(())
+}
+
+// Outgoing links of every document, indexed by doc id - 1. Links to pages outside the crawl,
+// to the page itself and repeated links are dropped
+fn build_link_graph(documents: Vec<IDBookElement>) -> Vec<Vec<DocId>> {
+    let url_to_id: Arc<HashMap<String, DocId>> = Arc::new(
+        documents
+            .iter()
+            .map(|document| (normalize_url(&document.url), document.id))
+            .collect(),
+    );
+    let document_count = documents.len();
+    let chunk_size =
+        document_count.div_ceil(thread::available_parallelism().map_or(4, |n| n.get()));
+
+    let mut handles = vec![];
+    for chunk in documents.chunks(chunk_size) {
+        let chunk: Vec<(DocId, PathBuf)> = chunk
+            .iter()
+            .map(|document| (document.id, document.path.clone()))
+            .collect();
+        let url_to_id = Arc::clone(&url_to_id);
+        handles.push(thread::spawn(move || {
+            chunk
+                .into_iter()
+                .map(|(id, path)| (id, outgoing_links(id, &path, &url_to_id)))
+                .collect::<Vec<_>>()
+        }));
     }
 
+    let mut links = vec![Vec::new(); document_count];
     for handle in handles {
-        handle.join().unwrap();
+        for (id, targets) in handle.join().unwrap() {
+            links[id as usize - 1] = targets;
+        }
     }
+    links
+}
 
-    println!("Files with size > 20MB: {:?}", *twty.lock().unwrap());
-    println!("Files with size > 5MB: {:?}", *five.lock().unwrap());
+fn outgoing_links(id: DocId, path: &Path, url_to_id: &HashMap<String, DocId>) -> Vec<DocId> {
+    let page: Page = match fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+    {
+        Some(page) => page,
+        None => {
+            println!("Warning: could not read {}", path.display());
+            return Vec::new();
+        }
+    };
+    let Ok(base) = Url::parse(&page.url) else {
+        return Vec::new();
+    };
+    let document = scraper::Html::parse_document(&page.content);
+    let selector = scraper::Selector::parse("a[href]").unwrap();
+    let mut targets: Vec<DocId> = document
+        .select(&selector)
+        .filter_map(|link| link.value().attr("href"))
+        .filter_map(|href| resolve_link(&base, href))
+        .filter_map(|target| url_to_id.get(&target).copied())
+        .filter(|&target| target != id)
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    targets
 }
 
-fn get_files_in_dir(dir: &str) -> Vec<String> {
-    let mut files = Vec::new();
-    let paths = fs::read_dir(dir).unwrap();
-
-    for path in paths {
-        let path = path.unwrap().path();
-        let path_str = path.to_str().unwrap().to_string();
-        if path.is_dir() {
-            files.append(&mut get_files_in_dir(&path_str));
-        } else {
-            files.push(path_str);
+// Power iteration. A dangling page (no outgoing links) spreads its score over every page,
+// so the scores always sum to 1
+fn page_rank(
+    links: &[Vec<DocId>],
+    damping: f64,
+    max_iterations: usize,
+    tolerance: f64,
+) -> Vec<f64> {
+    let page_count = links.len() as f64;
+    let mut scores = vec![1.0 / page_count; links.len()];
+    for iteration in 1..=max_iterations {
+        let dangling: f64 = links
+            .iter()
+            .zip(&scores)
+            .filter(|(targets, _)| targets.is_empty())
+            .map(|(_, score)| score)
+            .sum();
+        let base = (1.0 - damping) / page_count + damping * dangling / page_count;
+        let mut next = vec![base; links.len()];
+        for (targets, score) in links.iter().zip(&scores) {
+            let share = damping * score / targets.len() as f64;
+            for &target in targets {
+                next[target as usize - 1] += share;
+            }
+        }
+        let change: f64 = next
+            .iter()
+            .zip(&scores)
+            .map(|(next, score)| (next - score).abs())
+            .sum();
+        scores = next;
+        if change < tolerance {
+            println!("PageRank converged after {} iterations", iteration);
+            break;
         }
     }
-
-    files
+    scores
 }
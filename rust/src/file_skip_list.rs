@@ -4,9 +4,10 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::postings::Postings;
+use crate::id_book::DocumentLengths;
+use crate::postings::{read_record, PostingBlocks, PostingCursor, Postings, PostingsCodec};
+use crate::single_posting::{DocId, Posting};
 
-pub const MERGED_INDEX_DIR: &str = "inverted_index/merged";
 const PARTITION: u16 = 100;
 
 pub struct FileSkip {
@@ -15,10 +16,10 @@ pub struct FileSkip {
     byte_offset: u64,
 }
 
-type FileSkipList = Vec<FileSkip>;
+pub type FileSkipList = Vec<FileSkip>;
 
 impl FileSkip {
-    pub fn build_skip_list(path: PathBuf) -> FileSkipList {
+    pub fn build_skip_list(path: PathBuf, codec: PostingsCodec) -> FileSkipList {
         let initial_character: char = path
             .file_stem()
             .unwrap()
@@ -46,6 +47,25 @@ impl FileSkip {
         // It also provides no advantage when reading from a source that is already in memory, like a Vec<u8>.
 
         let mut skip_list: FileSkipList = Vec::new();
+        if codec != PostingsCodec::Text {
+            // binary records can't be read as lines, step over them one by one instead
+            let mut record_count: u32 = 0;
+            loop {
+                let current_position = reader.stream_position().unwrap();
+                let Ok(Some((word, _))) = read_record(&mut reader) else {
+                    break;
+                };
+                if record_count.is_multiple_of(PARTITION as u32) {
+                    skip_list.push(FileSkip {
+                        character: initial_character,
+                        word,
+                        byte_offset: current_position,
+                    });
+                }
+                record_count += 1;
+            }
+            return skip_list;
+        }
         let mut line_count: u32 = 0;
         let mut line: String = String::new();
         // read_line appends to the provided buffer, gets the line from the buffer and returns the number of bytes read
@@ -60,7 +80,7 @@ impl FileSkip {
             let current_position = reader.stream_position().unwrap() - bytes_read as u64;
 
             // for every partition-th line, add a skip entry!!
-            if line_count % PARTITION as u32 == 0 {
+            if line_count.is_multiple_of(PARTITION as u32) {
                 if let Some(word) = line.split(':').next() {
                     skip_list.push(FileSkip {
                         character: initial_character,
@@ -77,18 +97,18 @@ impl FileSkip {
         skip_list
     }
 
-    pub fn write_skip_list(skip_list: &FileSkipList) {
+    // merged_dir is the directory holding the merged file the skip list was built from
+    pub fn write_skip_list(merged_dir: &Path, skip_list: &FileSkipList) {
         if skip_list.is_empty() {
             return;
         }
 
-        let path = Path::new(MERGED_INDEX_DIR);
-        if !path.exists() {
-            std::fs::create_dir_all(path).unwrap();
+        if !merged_dir.exists() {
+            std::fs::create_dir_all(merged_dir).unwrap();
         }
 
         let character = skip_list[0].character;
-        let file_path = format!("{}/{}_skiplist.txt", MERGED_INDEX_DIR, character);
+        let file_path = merged_dir.join(format!("{}_skiplist.txt", character));
         let mut file = File::create(file_path).unwrap();
 
         // Write skip list entries to file
@@ -102,9 +122,9 @@ impl FileSkip {
         }
     }
 
-    pub fn read_skip_list(character: char) -> FileSkipList {
-        let file_path = format!("{}/{}_skiplist.txt", MERGED_INDEX_DIR, character);
-        let path = Path::new(&file_path);
+    pub fn read_skip_list(merged_dir: &Path, character: char) -> FileSkipList {
+        let file_path = merged_dir.join(format!("{}_skiplist.txt", character));
+        let path = file_path.as_path();
 
         if !path.exists() {
             return Vec::new();
@@ -142,9 +162,7 @@ impl FileSkip {
 
         let mut prev_offset = 0;
 
-        for i in 0..skip_list.len() {
-            let skip = &skip_list[i];
-
+        for (i, skip) in skip_list.iter().enumerate() {
             if &skip.word == word {
                 return WordOffsetRange::Exact(skip.byte_offset);
             }
@@ -167,6 +185,135 @@ impl FileSkip {
         WordOffsetRange::After(prev_offset)
     }
 }
+// skip lists of every merged file in merged_dir, indexed by first_char_index
+pub fn read_skip_lists(merged_dir: &Path) -> Vec<FileSkipList> {
+    ('0'..='9')
+        .chain('a'..='z')
+        .map(|character| FileSkip::read_skip_list(merged_dir, character))
+        .collect()
+}
+
+// position of a term's merged file among the skip lists, digits first
+pub fn first_char_index(first_char: char) -> usize {
+    if first_char.is_ascii_digit() {
+        (first_char as u8 - b'0') as usize
+    } else {
+        (first_char as u8 - b'a') as usize + 10
+    }
+}
+
+// Looks a term up in the merged index at merged_dir, empty when it isn't there
+pub fn lookup_postings(
+    skiplists: &[FileSkipList],
+    merged_dir: &Path,
+    term: &str,
+    codec: PostingsCodec,
+) -> Postings {
+    let first_char = term.chars().next().unwrap();
+    if codec == PostingsCodec::Text {
+        let offset_range =
+            FileSkip::find_skip_entry(&skiplists[first_char_index(first_char)], &term.to_string());
+        let file_path = merged_dir.join(format!("{}.txt", first_char));
+        return match File::open(&file_path) {
+            Ok(file) => get_postings_from_offset_range(&file, offset_range, term),
+            Err(_) => {
+                println!("Warning: Could not open index file for '{}'", first_char);
+                Postings::new(term.to_string())
+            }
+        };
+    }
+    lookup_payload(skiplists, merged_dir, term, codec)
+        .and_then(|payload| {
+            Postings::decode(term.to_string(), &payload, codec)
+                .inspect_err(|e| {
+                    println!(
+                        "Warning: could not decode the postings of '{}': {}",
+                        term, e
+                    )
+                })
+                .ok()
+        })
+        .unwrap_or_else(|| Postings::new(term.to_string()))
+}
+
+// Cursor over a term's postings, decoding them only as it moves. Text postings have no skip
+// pointers and are decoded up front, their score bound is worked out from the document lengths
+pub fn lookup_cursor(
+    skiplists: &[FileSkipList],
+    merged_dir: &Path,
+    term: &str,
+    codec: PostingsCodec,
+    lengths: &DocumentLengths,
+) -> PostingCursor {
+    if codec == PostingsCodec::Text {
+        return PostingCursor::from_postings(
+            lookup_postings(skiplists, merged_dir, term, codec).postings,
+            lengths,
+        );
+    }
+    lookup_payload(skiplists, merged_dir, term, codec)
+        .and_then(|payload| {
+            PostingCursor::new(payload, codec)
+                .inspect_err(|e| {
+                    println!(
+                        "Warning: could not decode the postings of '{}': {}",
+                        term, e
+                    )
+                })
+                .ok()
+        })
+        .unwrap_or_else(|| PostingCursor::from_postings(Vec::new(), lengths))
+}
+
+// Postings of a term for the given documents (sorted) only. With the block codec only the blocks
+// that may hold one of them are decoded
+pub fn lookup_postings_of(
+    skiplists: &[FileSkipList],
+    merged_dir: &Path,
+    term: &str,
+    codec: PostingsCodec,
+    doc_ids: &[DocId],
+) -> Vec<Posting> {
+    if codec != PostingsCodec::Block {
+        let mut postings = lookup_postings(skiplists, merged_dir, term, codec).postings;
+        postings.retain(|posting| doc_ids.binary_search(&posting.doc_id).is_ok());
+        return postings;
+    }
+    lookup_payload(skiplists, merged_dir, term, codec)
+        .and_then(|payload| {
+            PostingBlocks::parse(&payload)
+                .and_then(|blocks| blocks.postings_of(&payload, doc_ids))
+                .inspect_err(|e| {
+                    println!(
+                        "Warning: could not decode the postings of '{}': {}",
+                        term, e
+                    )
+                })
+                .ok()
+        })
+        .unwrap_or_default()
+}
+
+// encoded postings of a term in a binary merged index, None when it isn't there
+fn lookup_payload(
+    skiplists: &[FileSkipList],
+    merged_dir: &Path,
+    term: &str,
+    codec: PostingsCodec,
+) -> Option<Vec<u8>> {
+    let first_char = term.chars().next().unwrap();
+    let offset_range =
+        FileSkip::find_skip_entry(&skiplists[first_char_index(first_char)], &term.to_string());
+    let file_path = merged_dir.join(format!("{}.{}", first_char, codec.extension()));
+    match File::open(&file_path) {
+        Ok(file) => get_payload_from_offset_range(&file, offset_range, term),
+        Err(_) => {
+            println!("Warning: Could not open index file for '{}'", first_char);
+            None
+        }
+    }
+}
+
 pub enum WordOffsetRange {
     Invalid,
     Exact(u64),        // Word is an exact match
@@ -224,6 +371,28 @@ pub fn get_postings_from_offset_range(
     Postings {
         word: word.to_string(),
         postings,
-        skip_list: Vec::new(),
+    }
+}
+
+// Terms are sorted, so the scan stops at the first record past the word
+fn get_payload_from_offset_range(
+    file: &File,
+    offset_range: WordOffsetRange,
+    word: &str,
+) -> Option<Vec<u8>> {
+    let start_offset = match offset_range {
+        WordOffsetRange::Exact(offset)
+        | WordOffsetRange::Between(offset, _)
+        | WordOffsetRange::After(offset) => offset,
+        WordOffsetRange::Invalid => return None,
+    };
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(start_offset)).unwrap();
+    loop {
+        match read_record(&mut reader) {
+            Ok(Some((term, payload))) if term == word => return Some(payload),
+            Ok(Some((term, _))) if term.as_str() < word => continue,
+            _ => return None,
+        }
     }
 }
@@ -1,16 +1,46 @@
-use crate::index_builder::IDBOOK_PATH;
+use crate::config::IndexConfig;
+use crate::index_metadata::BuildStamp;
+use crate::single_posting::DocId;
 use std::{
     io::{BufRead, Seek},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
+use url::Url;
+
+// The id book is written with '/' separators regardless of the OS that built it,
+// so an index built on Windows can be served from Linux and vice versa
+pub fn normalize_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+// Absolute form of a link target without its fragment, so it can be compared to crawled urls
+pub fn resolve_link(base: &Url, href: &str) -> Option<String> {
+    let mut target = base.join(href.trim()).ok()?;
+    if !matches!(target.scheme(), "http" | "https") {
+        return None;
+    }
+    target.set_fragment(None);
+    Some(target.to_string())
+}
+
+pub fn normalize_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut url) => {
+            url.set_fragment(None);
+            url.to_string()
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
 pub struct IDBookElement {
-    pub id: u16,
+    pub id: DocId,
     pub url: String,
     pub path: PathBuf,
 }
 
 impl IDBookElement {
-    pub fn new(id: u16, url: String, path: PathBuf) -> Self {
+    pub fn new(id: DocId, url: String, path: PathBuf) -> Self {
         Self { id, url, path }
     }
 
@@ -23,15 +53,33 @@ impl IDBookElement {
         }
     }
 
-    pub fn idbook_element_from_string(id: u16, line: &str) -> Self {
+    pub fn idbook_element_from_string(id: DocId, line: &str) -> Self {
         let mut parts = line.splitn(2, '|');
         let url = parts.next().unwrap().trim().to_string();
-        let path = PathBuf::from(parts.next().unwrap().trim().to_string());
+        // older id books were written on windows with '\\' separators
+        let path = PathBuf::from(parts.next().unwrap().trim().replace('\\', "/"));
         Self::new(id, url, path)
     }
 
-    pub fn get_doc_from_id(id: u16) -> Self {
-        let buffer = std::fs::File::open(IDBOOK_PATH).unwrap();
+    // every line in the id book is exactly 400 bytes, so the file length gives the document count
+    pub fn document_count(config: &IndexConfig) -> DocId {
+        std::fs::metadata(config.id_book_path())
+            .map(|metadata| (metadata.len() / 400) as DocId)
+            .unwrap_or(0)
+    }
+
+    // every entry in doc id order, for passes over the whole corpus
+    pub fn read_all(config: &IndexConfig) -> std::io::Result<Vec<Self>> {
+        let reader = std::io::BufReader::new(std::fs::File::open(config.id_book_path())?);
+        let mut elements = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            elements.push(Self::idbook_element_from_string(index as DocId + 1, &line?));
+        }
+        Ok(elements)
+    }
+
+    pub fn get_doc_from_id(config: &IndexConfig, id: DocId) -> Self {
+        let buffer = std::fs::File::open(config.id_book_path()).unwrap();
         // skip 400  * (id - 1) bytes
         let mut reader = std::io::BufReader::new(buffer);
         reader
@@ -42,3 +90,94 @@ impl IDBookElement {
         Self::idbook_element_from_string(id, &line)
     }
 }
+
+// Token length of every document, stored next to the id book as little-endian u32s
+// in doc id order (doc 1 is the first entry), so it can be loaded whole at startup
+pub struct DocumentLengths {
+    lengths: Vec<u32>,
+}
+
+impl DocumentLengths {
+    pub fn new(lengths: Vec<u32>) -> Self {
+        Self { lengths }
+    }
+
+    pub fn get(&self, id: DocId) -> u32 {
+        self.lengths
+            .get((id as usize).wrapping_sub(1))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn load(config: &IndexConfig) -> std::io::Result<Self> {
+        let bytes = std::fs::read(config.document_lengths_path())?;
+        let lengths = bytes
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        Ok(Self { lengths })
+    }
+
+    pub fn save(&self, config: &IndexConfig) -> std::io::Result<()> {
+        let bytes: Vec<u8> = self
+            .lengths
+            .iter()
+            .flat_map(|length| length.to_le_bytes())
+            .collect();
+        std::fs::write(config.document_lengths_path(), bytes)
+    }
+}
+
+// A float per document (e.g. the tf-idf vector length), stored like DocumentLengths
+// as little-endian f64s in doc id order
+pub struct DocumentWeights {
+    weights: Vec<f64>,
+}
+
+impl DocumentWeights {
+    pub fn new(weights: Vec<f64>) -> Self {
+        Self { weights }
+    }
+
+    pub fn get(&self, id: DocId) -> f64 {
+        self.weights
+            .get((id as usize).wrapping_sub(1))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::from_bytes(&std::fs::read(path)?))
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    // for weights computed outside the build, see BuildStamp
+    pub fn load_stamped(path: &Path, stamp: BuildStamp) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Ok(Self::from_bytes(stamp.check(&bytes, path)?))
+    }
+
+    pub fn save_stamped(&self, path: &Path, stamp: BuildStamp) -> std::io::Result<()> {
+        let mut bytes = stamp.to_bytes().to_vec();
+        bytes.extend(self.to_bytes());
+        std::fs::write(path, bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let weights = bytes
+            .chunks_exact(8)
+            .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        Self { weights }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.weights
+            .iter()
+            .flat_map(|weight| weight.to_le_bytes())
+            .collect()
+    }
+}
@@ -1,18 +1,25 @@
+use crate::config::IndexConfig;
+use crate::document_store::{DocumentStoreWriter, StoredDocument};
+use crate::id_book::{normalize_path, normalize_url, resolve_link, DocumentLengths};
+use crate::index_metadata::IndexMetadata;
 use crate::inverted_index;
+use crate::similarity::{self, DuplicateClusters, DuplicateDetector};
+use crate::single_posting::{DocId, Field};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
-use std::io::Write;
-use std::os::windows::fs::MetadataExt;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time;
+use url::Url;
 use url_parse::core::Parser;
-const PATH: &str = "../developer/DEV/";
-pub const IDBOOK_PATH: &str = "inverted_index/id_book.txt";
-pub const BATCH_SIZE: u16 = 5000; // Define the batch size
+
+// (doc_id, store record, extracted text) sent from the file workers to the indexing thread,
+// which fills in the record's text and length
+type DocumentMessage = (DocId, StoredDocument, PageText);
 #[derive(Debug, Deserialize)]
 
 pub struct Document {
@@ -21,7 +28,27 @@ pub struct Document {
     encoding: String,
 }
 
-fn get_only_text_from_html(content: &str, encoding: String) -> String {
+// Text of a page, the body plus the text of each field it has
+#[derive(Debug, Default)]
+pub struct PageText {
+    pub body: String,
+    pub fields: Vec<(Field, String)>,
+    // (resolved target url, link text) of every link on the page
+    pub links: Vec<(String, String)>,
+}
+
+impl PageText {
+    // text of the page's first title element
+    pub fn title(&self) -> Option<String> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == Field::Title)
+            .map(|(_, title)| title.split_whitespace().collect::<Vec<&str>>().join(" "))
+            .filter(|title| !title.is_empty())
+    }
+}
+
+fn get_only_text_from_html(content: &str, encoding: String, url: &str) -> PageText {
     let ascii_content: String = if encoding.to_lowercase().contains("ascii") {
         content.chars().filter(|c| c.is_ascii()).collect::<String>()
     } else {
@@ -36,132 +63,216 @@ fn get_only_text_from_html(content: &str, encoding: String) -> String {
 
     let selector = scraper::Selector::parse("body")
         .unwrap_or_else(|_| scraper::Selector::parse("html").unwrap());
-    // Define selectors with their priority weights
-    let priority_selectors = [
-        ("title", 10),
-        ("h1", 8),
-        ("h2", 6),
-        ("h3", 5),
-        ("h4", 4),
-        ("h5", 3),
-        ("h6", 2),
-        ("strong", 2),
-        ("b", 1),
+    // important elements are indexed as fields and weighted at query time
+    let field_selectors = [
+        ("title", Field::Title),
+        ("h1, h2, h3, h4, h5, h6", Field::Heading),
+        ("strong, b", Field::Emphasis),
     ];
 
-    let mut combined_text = String::new();
-
-    // the body text first
+    let mut page = PageText::default();
     if let Some(body) = document.select(&selector).next() {
-        combined_text.push_str(&body.text().collect::<String>());
-
-        // priority-based repetition for important elements
-        for (tag, weight) in priority_selectors {
-            if let Ok(sel) = scraper::Selector::parse(tag) {
+        // text nodes are joined with a space so a heading and the paragraph after it don't run
+        // into one word
+        page.body = body.text().collect::<Vec<&str>>().join(" ");
+        for (tags, field) in field_selectors {
+            if let Ok(sel) = scraper::Selector::parse(tags) {
                 for element in document.select(&sel) {
                     let text = element.text().collect::<String>();
-                    for _ in 0..weight * 5 {
-                        combined_text.push_str(&text);
-                    }
+                    page.fields.push((field, text));
                 }
             }
         }
-
-        combined_text
-    } else {
-        String::new()
     }
+    // the link text describes the page it points at, not this one
+    if let (Ok(base), Ok(links)) = (Url::parse(url), scraper::Selector::parse("a[href]")) {
+        for link in document.select(&links) {
+            let text = link.text().collect::<String>();
+            if text.trim().is_empty() {
+                continue;
+            }
+            if let Some(target) = link
+                .value()
+                .attr("href")
+                .and_then(|href| resolve_link(&base, href))
+            {
+                page.links.push((target, text));
+            }
+        }
+    }
+    page
 }
 
+// a file that can't be read fails the build, one that isn't a crawled page is skipped
 fn process_file(
     file_path: PathBuf,
-    tx_clone: Sender<(u16, String, String)>,
-    id_book_clone: Arc<Mutex<HashMap<u16, (String, String)>>>,
-    doc_id: Arc<Mutex<u16>>,
-) {
+    tx_clone: Sender<DocumentMessage>,
+    id_book_clone: Arc<Mutex<HashMap<DocId, (String, String)>>>,
+    doc_id: Arc<Mutex<DocId>>,
+    duplicates: Arc<Mutex<DuplicateDetector>>,
+) -> io::Result<()> {
+    let with_path =
+        |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", file_path.display(), e));
     // ! check if the file is valid here
-    let file = fs::File::open(&file_path).unwrap();
-    if file.metadata().unwrap().file_size() > 5_000_000 {
-        return;
+    // metadata().len() is portable, unlike the windows-only MetadataExt::file_size()
+    if fs::metadata(&file_path).map_err(with_path)?.len() > 5_000_000 {
+        return Ok(());
     }
-    let content: String = fs::read_to_string(&file_path).unwrap();
+    let content: String = fs::read_to_string(&file_path).map_err(with_path)?;
 
     let doc: Document = if let Ok(doc) = serde_json::from_str(&content) {
         doc
     } else {
-        return;
+        return Ok(());
     };
     let url: String = doc.url.clone();
     if !is_valid_page(&url, &doc.content) {
-        return;
+        return Ok(());
     }
     // ! do some logic if there is a query as well perhaps since it could be bad for us
-    let text: String = get_only_text_from_html(&doc.content, doc.encoding);
+    let text: PageText = get_only_text_from_html(&doc.content, doc.encoding.clone(), &url);
+    // mirrored pages are dropped, near duplicates are indexed but remember their cluster
+    let clean_text = similarity::clean_text(&text.body);
+    let fingerprint = similarity::simhash(&clean_text);
+    // the detector stays locked until the doc id is handed out, so two copies can't both get in
+    let mut duplicates = duplicates.lock().unwrap();
+    if !clean_text.is_empty() && duplicates.is_exact_duplicate(&clean_text) {
+        return Ok(());
+    }
     // Send the processed document data to the main thread
     let mut doc_id = doc_id.lock().unwrap();
     *doc_id += 1;
-    tx_clone.send((*doc_id, doc.url.clone(), text)).unwrap();
+    if let Some(fingerprint) = fingerprint {
+        duplicates.insert(*doc_id, fingerprint);
+    }
+    drop(duplicates);
+    let record = StoredDocument {
+        url: doc.url.clone(),
+        title: text.title(),
+        encoding: doc.encoding,
+        content_bytes: doc.content.len() as u64,
+        source_path: normalize_path(&file_path),
+        ..StoredDocument::default()
+    };
+    tx_clone.send((*doc_id, record, text)).unwrap();
     // Update id_book
     let mut id_book = id_book_clone.lock().unwrap();
-    id_book.insert(*doc_id, (doc.url, file_path.to_str().unwrap().to_string()));
+    id_book.insert(*doc_id, (doc.url, normalize_path(&file_path)));
+    Ok(())
 }
 
-pub fn main() -> u16 {
-    let doc_id: Arc<Mutex<u16>> = Arc::new(Mutex::new(0));
+// The crawled pages, one directory per domain with a json file per page. Anything else in the
+// corpus directory is skipped
+fn corpus_files(corpus_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let domains = fs::read_dir(corpus_dir).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("corpus directory {}: {}", corpus_dir.display(), e),
+        )
+    })?;
+    for domain in domains {
+        let domain = domain?;
+        if !domain.file_type()?.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(domain.path())? {
+            let file = file?;
+            if file.file_type()?.is_file() {
+                files.push(file.path());
+            } else {
+                println!("Skipping {}, not a file", file.path().display());
+            }
+        }
+    }
+    Ok(files)
+}
+
+// returns the number of documents indexed
+pub fn main(config: &IndexConfig) -> io::Result<DocId> {
+    let batch_size = config.batch_size as usize;
+    let max_ngram = config.max_ngram.max(1);
+    let files = corpus_files(&config.corpus_dir)?;
+    config.write_format_version()?;
+    // computed from the doc ids of the previous build, which this one hands out afresh
+    for path in [config.page_rank_path(), config.link_graph_path()] {
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                println!("Error removing {}: {}", path.display(), e);
+            }
+        }
+    }
+    let doc_id: Arc<Mutex<DocId>> = Arc::new(Mutex::new(0));
+    let duplicates = Arc::new(Mutex::new(DuplicateDetector::new()));
     // shared between threads
-    let id_book: Arc<Mutex<HashMap<u16, (String, String)>>> = Arc::new(Mutex::new(HashMap::new()));
-    let inverted_indexes: Arc<Mutex<inverted_index::InvertedIndexSplit>> =
-        Arc::new(Mutex::new(inverted_index::InvertedIndexSplit::new()));
+    let id_book: Arc<Mutex<HashMap<DocId, (String, String)>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+    let inverted_indexes: Arc<Mutex<inverted_index::InvertedIndexSplit>> = Arc::new(Mutex::new(
+        inverted_index::InvertedIndexSplit::with_ngrams(max_ngram),
+    ));
     let time = time::Instant::now();
     // https://doc.rust-lang.org/book/ch16-02-message-passing.html once you make an index, send it to the main thread to write to disk
     // Create a channel to send data from threads to the main thread
-    let (tx, rx): (
-        Sender<(u16, String, String)>,
-        Receiver<(u16, String, String)>,
-    ) = channel();
+    let (tx, rx): (Sender<DocumentMessage>, Receiver<DocumentMessage>) = channel();
 
     let mut handles = vec![]; // Vector to store thread handles
-    for dir_entry in fs::read_dir(PATH).unwrap() {
-        let dir = dir_entry.unwrap();
-        // Iterate over the files in the directory
-        // Will error if dir ever contains a non-directory (a file)
-        for file in fs::read_dir(dir.path()).unwrap() {
-            let file: fs::DirEntry = file.unwrap();
-            // assumption is file is a json file
-            let filepath = file.path();
-            // each sender, needs a way to send, so clone the sender
-            let tx_clone = tx.clone();
-            // https://doc.rust-lang.org/book/ch16-03-shared-state.html#atomic-reference-counting-with-arct
-            // Clone the Arc to share ownership between threads
-            let id_book_clone = Arc::clone(&id_book);
-            let doc_id_clone = Arc::clone(&doc_id);
-            let handle = thread::spawn(move || {
-                process_file(filepath, tx_clone, id_book_clone, doc_id_clone);
-            });
+    for filepath in files {
+        // assumption is file is a json file
+        // each sender, needs a way to send, so clone the sender
+        let tx_clone = tx.clone();
+        // https://doc.rust-lang.org/book/ch16-03-shared-state.html#atomic-reference-counting-with-arct
+        // Clone the Arc to share ownership between threads
+        let id_book_clone = Arc::clone(&id_book);
+        let doc_id_clone = Arc::clone(&doc_id);
+        let duplicates_clone = Arc::clone(&duplicates);
+        let handle = thread::spawn(move || {
+            process_file(
+                filepath,
+                tx_clone,
+                id_book_clone,
+                doc_id_clone,
+                duplicates_clone,
+            )
+        });
 
-            handles.push(handle);
-        }
+        handles.push(handle);
     }
     // Drop the original sender to signal the end of sending
     drop(tx);
     // Process documents received from the threads
     let mut batch_count = 0;
+    let mut total_token_count: u64 = 0;
+    let mut document_lengths: HashMap<DocId, u32> = HashMap::new();
+    // (source doc id, target url, link text), indexed once every page has a doc id
+    let mut anchors: Vec<(DocId, String, String)> = Vec::new();
+    let mut document_store = DocumentStoreWriter::create(config)?;
     // The loop needs to terminate when all senders are dropped.
     // The `recv()` method returns a `Result`, with `Err` indicating that the channel is closed.
-    while let Ok((id, _url, text)) = rx.recv() {
+    while let Ok((id, mut record, text)) = rx.recv() {
         let mut inverted_indexes_locked = inverted_indexes.lock().unwrap();
-        inverted_indexes_locked.add_document(id, &text);
+        let token_count = inverted_indexes_locked.add_document(id, &text.body, &text.fields);
+        total_token_count += token_count as u64;
+        document_lengths.insert(id, token_count as u32);
+        record.text = text.body;
+        record.length = token_count as u32;
+        if let Err(e) = document_store.add(id, &record) {
+            println!("Error writing doc_id {} to the document store: {}", id, e);
+        }
+        anchors.extend(
+            text.links
+                .into_iter()
+                .map(|(target, anchor_text)| (id, target, anchor_text)),
+        );
         batch_count += 1;
-        // Write to disk if we've processed BATCH_SIZE documents
-        if batch_count % BATCH_SIZE as usize == 0 {
-            match inverted_indexes_locked.write_to_disk(format!(
-                "inverted_index/{}",
-                batch_count / BATCH_SIZE as usize - 1
-            )) {
+        // Write to disk if we've processed batch_size documents
+        if batch_count % batch_size == 0 {
+            match inverted_indexes_locked
+                .write_to_disk(config.batch_dir(batch_count / batch_size - 1))
+            {
                 Ok(_) => println!("Successfully written batch to disk:"),
                 Err(e) => println!("Error writing to disk: {}", e),
             }
-            *inverted_indexes_locked = inverted_index::InvertedIndexSplit::new(); // Reset the index
+            *inverted_indexes_locked = inverted_index::InvertedIndexSplit::with_ngrams(max_ngram); // Reset the index
             println!(
                 "Processed {} documents in {} minutes",
                 batch_count,
@@ -171,19 +282,26 @@ pub fn main() -> u16 {
     }
 
     // Complete all threads before continuing to the main thread
+    let mut unreadable = None;
     for handle in handles {
-        if let Err(e) = handle.join() {
-            println!("Error joining thread: {:?}", e);
+        match handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                unreadable.get_or_insert(e);
+            }
+            Err(e) => println!("Error joining thread: {:?}", e),
         }
     }
+    if let Some(e) = unreadable {
+        return Err(e);
+    }
     let doc_id = *doc_id.lock().unwrap();
     // Write final batch if any documents remain
     let inverted_indexes_locked = inverted_indexes.lock().unwrap();
-    if batch_count % BATCH_SIZE as usize != 0 {
-        if let Err(e) = inverted_indexes_locked.write_to_disk(format!(
-            "inverted_index/{}",
-            batch_count / BATCH_SIZE as usize
-        )) {
+    if batch_count % batch_size != 0 {
+        if let Err(e) =
+            inverted_indexes_locked.write_to_disk(config.batch_dir(batch_count / batch_size))
+        {
             println!("Error writing final batch to disk: {}", e);
         } else {
             println!(
@@ -193,12 +311,16 @@ pub fn main() -> u16 {
         }
     }
 
+    if let Err(e) = document_store.finish(config, doc_id) {
+        println!("Error writing document store offsets: {}", e);
+    }
+
     // Write id_book to disk
     let id_book_locked = id_book.lock().unwrap();
     let mut sorted_entries: Vec<_> = id_book_locked.iter().collect();
     sorted_entries.sort_by_key(|&(k, _)| k);
 
-    match fs::File::create(IDBOOK_PATH) {
+    match fs::File::create(config.id_book_path()) {
         Ok(mut file) => {
             for (id, (url, filepath)) in sorted_entries {
                 let mut line = format!("{} | {}", url, filepath);
@@ -215,7 +337,52 @@ pub fn main() -> u16 {
         }
         Err(e) => println!("Error creating id_book file: {}", e),
     }
-    return doc_id;
+
+    let duplicates = duplicates.lock().unwrap();
+    println!(
+        "Dropped {} exact duplicates, found {} near duplicates",
+        duplicates.exact_count, duplicates.near_count
+    );
+    let clusters = (1..=doc_id).map(|id| duplicates.cluster(id)).collect();
+    if let Err(e) = DuplicateClusters::new(clusters).save(config) {
+        println!("Error writing duplicate clusters: {}", e);
+    }
+
+    // anchor text goes into its own partial index that the merger treats as one more batch
+    let url_to_id: HashMap<String, DocId> = id_book_locked
+        .iter()
+        .map(|(&id, (url, _))| (normalize_url(url), id))
+        .collect();
+    let mut anchor_index = inverted_index::InvertedIndexSplit::new();
+    let mut anchor_count = 0;
+    for (source, target, anchor_text) in anchors {
+        match url_to_id.get(&target) {
+            // links to the page itself are navigation, not a description
+            Some(&target) if target != source => {
+                anchor_index.add_field_text(target, Field::Anchor, &anchor_text);
+                anchor_count += 1;
+            }
+            _ => {}
+        }
+    }
+    match anchor_index.write_to_disk(config.anchor_dir()) {
+        Ok(_) => println!("Indexed anchor text of {} links", anchor_count),
+        Err(e) => println!("Error writing anchor text to disk: {}", e),
+    }
+
+    // doc ids are handed out contiguously from 1, so the lengths can be stored positionally
+    let lengths = (1..=doc_id)
+        .map(|id| document_lengths.get(&id).copied().unwrap_or(0))
+        .collect();
+    if let Err(e) = DocumentLengths::new(lengths).save(config) {
+        println!("Error writing document lengths: {}", e);
+    }
+
+    // unique_term_count is filled in by the merger
+    if let Err(e) = IndexMetadata::new(doc_id, total_token_count, max_ngram).save(config) {
+        println!("Error writing index metadata: {}", e);
+    }
+    Ok(doc_id)
 }
 
 fn is_valid_page(url: &str, content: &str) -> bool {
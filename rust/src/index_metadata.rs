@@ -0,0 +1,116 @@
+use crate::config::IndexConfig;
+use crate::postings::PostingsCodec;
+use crate::scorer::FieldWeights;
+use crate::single_posting::DocId;
+use crate::tokenizer::{Tokenizer, TokenizerSettings};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Corpus-wide statistics written next to the index so scoring never relies on hard-coded counts.
+// The builder fills in everything but the unique term count, which is only known after merging
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexMetadata {
+    pub document_count: DocId,
+    pub unique_term_count: u64,
+    pub total_token_count: u64,
+    pub average_document_length: f64,
+    // seconds since the unix epoch
+    pub build_timestamp: u64,
+    pub tokenizer: TokenizerSettings,
+    // longest n-gram in the n-gram index, 1 when the index has none
+    #[serde(default = "unigrams_only")]
+    pub max_ngram: usize,
+    // set by the merger, the partial indexes are always text
+    #[serde(default = "text_postings")]
+    pub postings_codec: PostingsCodec,
+    // field weights the merger computed the cosine document norms with, None before merging
+    #[serde(default)]
+    pub norm_field_weights: Option<FieldWeights>,
+}
+
+fn unigrams_only() -> usize {
+    1
+}
+
+fn text_postings() -> PostingsCodec {
+    PostingsCodec::Text
+}
+
+impl IndexMetadata {
+    pub fn new(document_count: DocId, total_token_count: u64, max_ngram: usize) -> Self {
+        let average_document_length = if document_count == 0 {
+            0.0
+        } else {
+            total_token_count as f64 / document_count as f64
+        };
+        Self {
+            document_count,
+            unique_term_count: 0,
+            total_token_count,
+            average_document_length,
+            build_timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+            tokenizer: Tokenizer::new().settings(),
+            max_ngram,
+            postings_codec: PostingsCodec::Text,
+            norm_field_weights: None,
+        }
+    }
+
+    pub fn stamp(&self) -> BuildStamp {
+        BuildStamp {
+            build_timestamp: self.build_timestamp,
+            document_count: self.document_count as u64,
+        }
+    }
+
+    pub fn load(config: &IndexConfig) -> io::Result<Self> {
+        let contents = fs::read_to_string(config.metadata_path())?;
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, config: &IndexConfig) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(config.metadata_path(), contents)
+    }
+}
+
+// Doc ids depend on the order the builder's threads finish in, so a file keyed by doc id that
+// is written after the build (PageRank, the link graph) starts with the stamp of that build
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuildStamp {
+    build_timestamp: u64,
+    document_count: u64,
+}
+
+impl BuildStamp {
+    pub const SIZE: usize = 16;
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.build_timestamp.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.document_count.to_le_bytes());
+        bytes
+    }
+
+    // the contents of a stamped file after the stamp, an error if another build wrote it
+    pub fn check<'a>(self, bytes: &'a [u8], path: &Path) -> io::Result<&'a [u8]> {
+        match bytes.split_at_checked(Self::SIZE) {
+            Some((stamp, rest)) if stamp == self.to_bytes() => Ok(rest),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} belongs to another build of the index, run the page_rank binary again",
+                    path.display()
+                ),
+            )),
+        }
+    }
+}
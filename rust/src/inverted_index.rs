@@ -1,9 +1,12 @@
+use crate::config::NGRAM_DIR;
 use crate::postings::Postings;
+use crate::single_posting::{DocId, Field};
 use crate::tokenizer::Tokenizer;
 use std::{
     collections::{BTreeSet, HashMap},
     fs::File,
     io::Write,
+    path::{Path, PathBuf},
 };
 
 #[derive(Clone)]
@@ -12,6 +15,12 @@ pub struct InvertedIndex {
     ordered_keys: BTreeSet<String>,
 }
 
+impl Default for InvertedIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[allow(dead_code)]
 impl InvertedIndex {
     pub fn new() -> InvertedIndex {
@@ -21,12 +30,30 @@ impl InvertedIndex {
         }
     }
 
-    pub fn insert(&mut self, term: String, doc_id: u16) {
+    pub fn insert(&mut self, term: String, doc_id: DocId, position: u32) {
+        let postings = self
+            .index
+            .entry(term.clone())
+            .or_insert(Postings::new(term.clone()));
+        postings.add_occurrence(doc_id, position);
+        self.ordered_keys.insert(term);
+    }
+
+    pub fn insert_count(&mut self, term: String, doc_id: DocId) {
+        let postings = self
+            .index
+            .entry(term.clone())
+            .or_insert(Postings::new(term.clone()));
+        postings.add_count(doc_id);
+        self.ordered_keys.insert(term);
+    }
+
+    pub fn insert_field(&mut self, term: String, doc_id: DocId, field: Field) {
         let postings = self
             .index
             .entry(term.clone())
             .or_insert(Postings::new(term.clone()));
-        postings.update_frequency(doc_id);
+        postings.add_field_occurrence(doc_id, field);
         self.ordered_keys.insert(term);
     }
 
@@ -43,9 +70,7 @@ impl InvertedIndex {
                 .index
                 .entry(term.clone())
                 .or_insert(Postings::new(term));
-            for posting in postings.get_postings() {
-                self_postings.update_frequency(posting.doc_id);
-            }
+            self_postings.merge(postings);
         }
     }
 }
@@ -61,6 +86,22 @@ pub struct InvertedIndexSplit {
     pub q_z: InvertedIndex,
     pub zero_nine: InvertedIndex,
     pub tokenizer: Tokenizer,
+    // word n-grams joined by NGRAM_SEPARATOR, split into partitions the same way as the unigrams
+    pub ngrams: Option<Box<InvertedIndexSplit>>,
+    pub max_ngram: usize,
+}
+
+// tokens are alphanumeric so this can never be part of one
+pub const NGRAM_SEPARATOR: char = '_';
+
+pub fn join_ngram(terms: &[String]) -> String {
+    terms.join(&NGRAM_SEPARATOR.to_string())
+}
+
+impl Default for InvertedIndexSplit {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InvertedIndexSplit {
@@ -71,37 +112,85 @@ impl InvertedIndexSplit {
             q_z: InvertedIndex::new(),
             zero_nine: InvertedIndex::new(),
             tokenizer: Tokenizer::new(),
+            ngrams: None,
+            max_ngram: 1,
+        }
+    }
+
+    // also indexes every run of 2..=max_ngram consecutive tokens
+    pub fn with_ngrams(max_ngram: usize) -> InvertedIndexSplit {
+        let mut index = InvertedIndexSplit::new();
+        if max_ngram >= 2 {
+            index.ngrams = Some(Box::new(InvertedIndexSplit::new()));
+            index.max_ngram = max_ngram;
+        }
+        index
+    }
+
+    fn partition(&mut self, term: &str) -> Option<&mut InvertedIndex> {
+        match term.chars().next().unwrap() {
+            'a'..='f' => Some(&mut self.a_f),
+            'g'..='p' => Some(&mut self.g_p),
+            'q'..='z' => Some(&mut self.q_z),
+            '0'..='9' => Some(&mut self.zero_nine),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, term: String, doc_id: DocId, position: u32) {
+        if let Some(index) = self.partition(&term) {
+            index.insert(term, doc_id, position);
         }
     }
 
-    pub fn add_document(&mut self, doc_id: u16, content: &str) {
+    fn insert_count(&mut self, term: String, doc_id: DocId) {
+        if let Some(index) = self.partition(&term) {
+            index.insert_count(term, doc_id);
+        }
+    }
+
+    pub fn add_field_text(&mut self, doc_id: DocId, field: Field, text: &str) {
+        for term in self.tokenizer.tokenize(text) {
+            if let Some(index) = self.partition(&term) {
+                index.insert_field(term, doc_id, field);
+            }
+        }
+    }
+
+    // returns the number of tokens in the body, which is what the document length counts
+    pub fn add_document(
+        &mut self,
+        doc_id: DocId,
+        content: &str,
+        fields: &[(Field, String)],
+    ) -> usize {
+        for (field, text) in fields {
+            self.add_field_text(doc_id, *field, text);
+        }
         let terms = self.tokenizer.tokenize(content);
-        for term in terms {
-            let first_char = term.chars().next().unwrap();
-            match first_char {
-                'a'..='f' => self.a_f.insert(term, doc_id),
-                'g'..='p' => self.g_p.insert(term, doc_id),
-                'q'..='z' => self.q_z.insert(term, doc_id),
-                '0'..='9' => self.zero_nine.insert(term, doc_id),
-                _ => {}
+        let token_count = terms.len();
+        if let Some(ngrams) = self.ngrams.as_mut() {
+            // the n-gram index is only asked which documents hold an n-gram, so its postings
+            // keep the count without the positions
+            for n in 2..=self.max_ngram {
+                for window in terms.windows(n) {
+                    ngrams.insert_count(join_ngram(window), doc_id);
+                }
             }
         }
+        for (position, term) in terms.into_iter().enumerate() {
+            self.insert(term, doc_id, position as u32);
+        }
+        token_count
     }
 
-    pub fn write_to_disk(&self, location: String) -> std::io::Result<()> {
+    pub fn write_to_disk(&self, location: PathBuf) -> std::io::Result<()> {
         // Helper function to convert an InvertedIndex to text format
-        fn write_index_to_file(index: &InvertedIndex, path: &str) -> std::io::Result<()> {
+        fn write_index_to_file(index: &InvertedIndex, path: &Path) -> std::io::Result<()> {
             let mut file = File::create(path)?;
             for term in index.get_ordered_keys() {
                 if let Some(postings) = index.get_postings(&term) {
-                    let postings_str: String = postings
-                        .get_postings()
-                        .iter()
-                        .map(|post| format!("{}|{}", post.doc_id, post.term_freq))
-                        .collect::<Vec<_>>()
-                        .join(",");
-
-                    writeln!(file, "{}: {}", term, postings_str)?;
+                    writeln!(file, "{}", postings.save_postings())?;
                 }
             }
             Ok(())
@@ -109,10 +198,13 @@ impl InvertedIndexSplit {
 
         std::fs::create_dir_all(&location)?;
 
-        write_index_to_file(&self.a_f, &format!("{}/a_f.txt", location))?;
-        write_index_to_file(&self.g_p, &format!("{}/g_p.txt", location))?;
-        write_index_to_file(&self.q_z, &format!("{}/q_z.txt", location))?;
-        write_index_to_file(&self.zero_nine, &format!("{}/0_9.txt", location))?;
+        write_index_to_file(&self.a_f, &location.join("a_f.txt"))?;
+        write_index_to_file(&self.g_p, &location.join("g_p.txt"))?;
+        write_index_to_file(&self.q_z, &location.join("q_z.txt"))?;
+        write_index_to_file(&self.zero_nine, &location.join("0_9.txt"))?;
+        if let Some(ngrams) = &self.ngrams {
+            ngrams.write_to_disk(location.join(NGRAM_DIR))?;
+        }
 
         Ok(())
     }
@@ -1,25 +1,108 @@
-use crate::index_builder::BATCH_SIZE;
-use crate::postings::Postings;
+use crate::config::IndexConfig;
+use crate::id_book::{DocumentLengths, DocumentWeights};
+use crate::index_metadata::IndexMetadata;
+use crate::postings::{Postings, PostingsCodec, PostingsReader, PostingsWriter};
+use crate::scorer::scoring_tf_idf;
+use crate::single_posting::DocId;
 use std::fs;
-use std::io::{BufRead, BufReader, LineWriter, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::file_skip_list::{FileSkip, MERGED_INDEX_DIR};
-pub fn main(doc_id: u16) {
-    fs::create_dir_all(MERGED_INDEX_DIR).unwrap_or_default();
-    let batch_count = (doc_id - 1) / BATCH_SIZE + 1;
+use crate::file_skip_list::FileSkip;
+pub fn main(config: &IndexConfig, doc_id: DocId) {
+    let batch_count = (doc_id - 1) / config.batch_size as DocId + 1;
+    let mut batch_dirs: Vec<PathBuf> = (0..batch_count as usize)
+        .map(|batch| config.batch_dir(batch))
+        .collect();
+    batch_dirs.push(config.anchor_dir());
+    // the score bounds of the binary codecs record the shortest document of each list
+    let lengths = match DocumentLengths::load(config) {
+        Ok(lengths) => Arc::new(lengths),
+        Err(e) => {
+            println!("Error reading document lengths: {}", e);
+            return;
+        }
+    };
+    // sum of squared tf-idf weights per document, accumulated while the postings stream past
+    let mut squared_norms: Vec<f64> = vec![0.0; doc_id as usize];
+    let codec = config.postings_codec;
+    let unique_term_count = merge_directory(
+        &batch_dirs,
+        &config.merged_dir(),
+        codec,
+        &lengths,
+        |postings| {
+            let document_frequency = postings.postings.len() as u32;
+            for posting in postings.get_postings() {
+                let term_freq = config
+                    .field_weights
+                    .weighted_freq(posting.term_freq, &posting.fields);
+                let weight = scoring_tf_idf(term_freq, document_frequency, doc_id);
+                squared_norms[posting.doc_id as usize - 1] += weight * weight;
+            }
+        },
+    );
+
+    let norms = squared_norms.into_iter().map(f64::sqrt).collect();
+    if let Err(e) = DocumentWeights::new(norms).save(&config.document_norms_path()) {
+        println!("Error writing document norms: {}", e);
+    }
+
+    let mut metadata = match IndexMetadata::load(config) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            println!("Error reading index metadata: {}", e);
+            return;
+        }
+    };
+    // the n-grams get their own merged files and skip lists, they don't count as terms
+    if metadata.max_ngram >= 2 {
+        let ngram_dirs: Vec<PathBuf> = (0..batch_count as usize)
+            .map(|batch| config.batch_ngram_dir(batch))
+            .collect();
+        let ngram_count = merge_directory(
+            &ngram_dirs,
+            &config.merged_ngram_dir(),
+            codec,
+            &lengths,
+            |_| {},
+        );
+        println!("Merged {} n-grams", ngram_count);
+    }
+
+    // the unique term count is only known once every batch has been merged
+    metadata.unique_term_count = unique_term_count;
+    metadata.postings_codec = codec;
+    metadata.norm_field_weights = Some(config.field_weights);
+    if let Err(e) = metadata.save(config) {
+        println!("Error writing index metadata: {}", e);
+    }
+}
+
+// Merges the partition files of every batch directory into one file per first character in
+// merged_dir, each with its own skip list. visit sees every merged term, returns the term count
+fn merge_directory(
+    batch_dirs: &[PathBuf],
+    merged_dir: &Path,
+    codec: PostingsCodec,
+    lengths: &Arc<DocumentLengths>,
+    mut visit: impl FnMut(&Postings),
+) -> u64 {
+    fs::create_dir_all(merged_dir).unwrap_or_default();
     let word_ranges = ["0_9", "a_f", "g_p", "q_z"];
     // first character is null
     let mut current_first_char = '\0';
-    let mut final_file_appender: Option<LineWriter<fs::File>> = None;
+    let mut final_file_appender: Option<PostingsWriter> = None;
+    let mut term_count: u64 = 0;
 
     for &words in &word_ranges {
         // Open all available files for this word range
-        let mut readers = Vec::with_capacity(batch_count as usize);
+        let mut readers = Vec::with_capacity(batch_dirs.len());
 
         // Open all available files for this word range
-        for i in 0..batch_count {
-            let filepath = format!("inverted_index/{}/{}.txt", i, words);
+        for batch_dir in batch_dirs {
+            let filepath = batch_dir.join(format!("{}.txt", words));
             if let Ok(file) = fs::File::open(&filepath) {
                 readers.push((BufReader::new(file), String::new()));
             }
@@ -41,8 +124,8 @@ pub fn main(doc_id: u16) {
         }
 
         while !postings_with_indices.is_empty() {
-            // find smallest word
-            postings_with_indices.sort_unstable_by(|a, b| a.0.word.cmp(&b.0.word));
+            // find smallest word, batches holding it are merged in batch order
+            postings_with_indices.sort_by(|a, b| a.0.word.cmp(&b.0.word).then(a.1.cmp(&b.1)));
             let smallest_word = postings_with_indices[0].0.word.clone();
 
             // first character check - create new file if needed
@@ -54,15 +137,13 @@ pub fn main(doc_id: u16) {
                 // Build and write skip list for previous character unless its starting character of null
                 if current_first_char != '\0' {
                     // build a skip list on that file
-                    let skip_list_path =
-                        PathBuf::from(format!("{}/{}.txt", MERGED_INDEX_DIR, current_first_char));
-                    let file_skip_list = FileSkip::build_skip_list(skip_list_path);
-                    FileSkip::write_skip_list(&file_skip_list);
+                    write_skip_list(merged_dir, current_first_char, codec);
                 }
                 // append the postings to the new file
                 current_first_char = first_char;
-                let file_path = format!("{}/{}.txt", MERGED_INDEX_DIR, current_first_char);
-                final_file_appender = Some(LineWriter::new(fs::File::create(file_path).unwrap()));
+                let file_path = merged_file_path(merged_dir, current_first_char, codec);
+                final_file_appender =
+                    Some(PostingsWriter::create(&file_path, codec, Arc::clone(lengths)).unwrap());
             }
 
             // merge all postings with the smallest word
@@ -83,10 +164,10 @@ pub fn main(doc_id: u16) {
 
             // Write merged posting
             if let Some(writer) = final_file_appender.as_mut() {
-                writer
-                    .write_all((merged_posting.save_postings() + "\n").as_bytes())
-                    .unwrap();
+                writer.write(&merged_posting).unwrap();
+                term_count += 1;
             }
+            visit(&merged_posting);
 
             // Read next lines for updated readers
             for &idx in &indices_to_update {
@@ -106,9 +187,116 @@ pub fn main(doc_id: u16) {
     }
 
     if current_first_char != '\0' {
-        let skip_list_path =
-            PathBuf::from(format!("{}/{}.txt", MERGED_INDEX_DIR, current_first_char));
-        let file_skip_list = FileSkip::build_skip_list(skip_list_path);
-        FileSkip::write_skip_list(&file_skip_list);
+        write_skip_list(merged_dir, current_first_char, codec);
+    }
+
+    term_count
+}
+
+fn merged_file_path(merged_dir: &Path, first_char: char, codec: PostingsCodec) -> PathBuf {
+    merged_dir.join(format!("{}.{}", first_char, codec.extension()))
+}
+
+fn write_skip_list(merged_dir: &Path, first_char: char, codec: PostingsCodec) {
+    let file_skip_list =
+        FileSkip::build_skip_list(merged_file_path(merged_dir, first_char, codec), codec);
+    FileSkip::write_skip_list(merged_dir, &file_skip_list);
+}
+
+// Rewrites the merged index (and its n-grams) in another codec, e.g. an index merged as text
+// into varbyte, without rebuilding it. Every partition is converted into a staging directory
+// first and only moved over the old ones once all of them succeeded, so a failure part way
+// leaves the index as it was
+pub fn convert(config: &IndexConfig, codec: PostingsCodec) -> io::Result<()> {
+    let mut metadata = IndexMetadata::load(config)?;
+    let from = metadata.postings_codec;
+    if from == codec {
+        println!("The index already uses the {:?} codec", codec);
+        return Ok(());
+    }
+    let lengths = Arc::new(DocumentLengths::load(config)?);
+    let mut merged_dirs = vec![config.merged_dir()];
+    if metadata.max_ngram >= 2 {
+        merged_dirs.push(config.merged_ngram_dir());
+    }
+    let mut sizes = Vec::new();
+    for merged_dir in &merged_dirs {
+        match convert_directory(merged_dir, from, codec, &lengths) {
+            Ok(size) => sizes.push(size),
+            Err(e) => {
+                for merged_dir in &merged_dirs {
+                    let _ = fs::remove_dir_all(staging_dir(merged_dir));
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    for (merged_dir, (before, after)) in merged_dirs.iter().zip(sizes) {
+        let staging_dir = staging_dir(merged_dir);
+        for entry in fs::read_dir(&staging_dir)? {
+            let entry = entry?;
+            fs::rename(entry.path(), merged_dir.join(entry.file_name()))?;
+        }
+        fs::remove_dir(&staging_dir)?;
+        println!(
+            "Converted {}: {} KB -> {} KB",
+            merged_dir.display(),
+            before as f64 / 1024.0,
+            after as f64 / 1024.0
+        );
+    }
+    metadata.postings_codec = codec;
+    metadata.save(config)?;
+
+    // the text codec's files have another extension, so the old ones are still there
+    if from.extension() != codec.extension() {
+        for merged_dir in &merged_dirs {
+            for first_char in ('0'..='9').chain('a'..='z') {
+                let old = merged_file_path(merged_dir, first_char, from);
+                if old.exists() {
+                    fs::remove_file(old)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn staging_dir(merged_dir: &Path) -> PathBuf {
+    merged_dir.with_extension("converting")
+}
+
+// writes every partition of the directory and its skip list in the new codec to the staging
+// directory, returns the size of the postings files before and after
+fn convert_directory(
+    merged_dir: &Path,
+    from: PostingsCodec,
+    to: PostingsCodec,
+    lengths: &Arc<DocumentLengths>,
+) -> io::Result<(u64, u64)> {
+    let staging_dir = staging_dir(merged_dir);
+    // left over from an interrupted conversion
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)?;
+    }
+    fs::create_dir_all(&staging_dir)?;
+    let mut sizes = (0, 0);
+    for first_char in ('0'..='9').chain('a'..='z') {
+        let source = merged_file_path(merged_dir, first_char, from);
+        if !source.exists() {
+            continue;
+        }
+        let target = merged_file_path(&staging_dir, first_char, to);
+        let mut reader = PostingsReader::open(&source, from)?;
+        let mut writer = PostingsWriter::create(&target, to, Arc::clone(lengths))?;
+        while let Some(postings) = reader.next_postings()? {
+            writer.write(&postings)?;
+        }
+        writer.flush()?;
+        sizes.0 += fs::metadata(&source)?.len();
+        sizes.1 += fs::metadata(&target)?.len();
+        write_skip_list(&staging_dir, first_char, to);
     }
+    Ok(sizes)
 }
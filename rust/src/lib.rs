@@ -0,0 +1,19 @@
+// The index, its builder and the query engine, shared by the search_engine and page_rank
+// binaries
+pub mod boolean_query;
+pub mod config;
+pub mod document_store;
+pub mod file_skip_list;
+pub mod id_book;
+pub mod index_builder;
+pub mod index_metadata;
+pub mod inverted_index;
+pub mod lazy_merger;
+pub mod link_graph;
+pub mod postings;
+pub mod query;
+pub mod scorer;
+pub mod similarity;
+pub mod single_posting;
+pub mod snippet;
+pub mod tokenizer;
@@ -0,0 +1,173 @@
+use crate::index_metadata::BuildStamp;
+use crate::single_posting::DocId;
+use std::{collections::HashMap, io, path::Path};
+
+// Links between crawled pages, written by the page_rank binary next to the index.
+// Stored as the stamp of the build its doc ids come from, then little-endian u32s: the page count, then page_count + 1 offsets into the targets,
+// then the targets, so the outgoing links of doc id d are targets[offsets[d - 1]..offsets[d]]
+pub struct LinkGraph {
+    offsets: Vec<u32>,
+    targets: Vec<DocId>,
+    // reverse of the above, built when loading
+    incoming: Vec<Vec<DocId>>,
+}
+
+impl LinkGraph {
+    // outgoing links of every document, indexed by doc id - 1
+    pub fn new(links: &[Vec<DocId>]) -> Self {
+        let mut offsets = Vec::with_capacity(links.len() + 1);
+        let mut targets = Vec::new();
+        offsets.push(0);
+        for page_links in links {
+            targets.extend_from_slice(page_links);
+            offsets.push(targets.len() as u32);
+        }
+        let incoming = reverse(&offsets, &targets);
+        Self {
+            offsets,
+            targets,
+            incoming,
+        }
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn outgoing(&self, id: DocId) -> &[DocId] {
+        let index = id as usize - 1;
+        match (self.offsets.get(index), self.offsets.get(index + 1)) {
+            (Some(&start), Some(&end)) => &self.targets[start as usize..end as usize],
+            _ => &[],
+        }
+    }
+
+    pub fn incoming(&self, id: DocId) -> &[DocId] {
+        self.incoming
+            .get(id as usize - 1)
+            .map_or(&[], |sources| sources.as_slice())
+    }
+
+    pub fn load(path: &Path, stamp: BuildStamp) -> io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let mut words = stamp
+            .check(&bytes, path)?
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated link graph");
+        let page_count = words.next().ok_or_else(invalid)? as usize;
+        let offsets: Vec<u32> = words.by_ref().take(page_count + 1).collect();
+        let targets: Vec<DocId> = words.collect();
+        if offsets.len() != page_count + 1 || *offsets.last().unwrap() as usize != targets.len() {
+            return Err(invalid());
+        }
+        // reverse() and outgoing() index with these, so a corrupt file must not get past here
+        if offsets[0] != 0 || offsets.windows(2).any(|window| window[0] > window[1]) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "link graph offsets out of order",
+            ));
+        }
+        if let Some(target) = targets
+            .iter()
+            .find(|&&target| target == 0 || target as usize > page_count)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("link graph target {target} outside 1..={page_count}"),
+            ));
+        }
+        let incoming = reverse(&offsets, &targets);
+        Ok(Self {
+            offsets,
+            targets,
+            incoming,
+        })
+    }
+
+    pub fn save(&self, path: &Path, stamp: BuildStamp) -> io::Result<()> {
+        let mut bytes = stamp.to_bytes().to_vec();
+        bytes.extend(
+            std::iter::once(self.page_count() as u32)
+                .chain(self.offsets.iter().copied())
+                .chain(self.targets.iter().copied())
+                .flat_map(|word| word.to_le_bytes()),
+        );
+        std::fs::write(path, bytes)
+    }
+}
+
+fn reverse(offsets: &[u32], targets: &[DocId]) -> Vec<Vec<DocId>> {
+    let mut incoming = vec![Vec::new(); offsets.len() - 1];
+    for (index, window) in offsets.windows(2).enumerate() {
+        for &target in &targets[window[0] as usize..window[1] as usize] {
+            incoming[target as usize - 1].push(index as DocId + 1);
+        }
+    }
+    incoming
+}
+
+// Kleinberg's HITS on the subgraph around the root documents: the root set is expanded with the
+// pages it links to and (up to max_incoming per page) the pages linking to it. Returns the
+// authority score of every root document, scaled so the best one is 1
+pub fn hits_authorities(
+    graph: &LinkGraph,
+    root: &[DocId],
+    max_incoming: usize,
+    iterations: usize,
+) -> HashMap<DocId, f64> {
+    let mut base: Vec<DocId> = root.to_vec();
+    for &id in root {
+        base.extend_from_slice(graph.outgoing(id));
+        base.extend(graph.incoming(id).iter().take(max_incoming));
+    }
+    base.sort_unstable();
+    base.dedup();
+    let position: HashMap<DocId, usize> = base.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+    // only the links inside the base set take part
+    let edges: Vec<(usize, usize)> = base
+        .iter()
+        .enumerate()
+        .flat_map(|(source, &id)| {
+            graph
+                .outgoing(id)
+                .iter()
+                .filter_map(|target| position.get(target))
+                .map(move |&target| (source, target))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut hubs = vec![1.0; base.len()];
+    let mut authorities = vec![1.0; base.len()];
+    for _ in 0..iterations {
+        authorities.iter_mut().for_each(|score| *score = 0.0);
+        for &(source, target) in &edges {
+            authorities[target] += hubs[source];
+        }
+        normalize(&mut authorities);
+        hubs.iter_mut().for_each(|score| *score = 0.0);
+        for &(source, target) in &edges {
+            hubs[source] += authorities[target];
+        }
+        normalize(&mut hubs);
+    }
+
+    let best = root
+        .iter()
+        .map(|id| authorities[position[id]])
+        .fold(0.0, f64::max);
+    root.iter()
+        .map(|&id| {
+            let authority = authorities[position[&id]];
+            (id, if best > 0.0 { authority / best } else { 0.0 })
+        })
+        .collect()
+}
+
+fn normalize(scores: &mut [f64]) {
+    let norm = scores.iter().map(|score| score * score).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        scores.iter_mut().for_each(|score| *score /= norm);
+    }
+}
@@ -1,42 +1,238 @@
-pub mod file_skip_list;
-pub mod id_book;
-pub mod index_builder;
-pub mod inverted_index;
-pub mod lazy_merger;
-pub mod postings;
-pub mod query;
-pub mod single_posting;
-pub mod tokenizer;
 use actix_cors::Cors;
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use clap::{Args, Parser, Subcommand};
+use search_engine::config::IndexConfig;
+use search_engine::{id_book, index_builder, index_metadata, lazy_merger, postings, query, scorer};
 use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-// fn main() {
-//     // ! BUILD INDEX
-//     let doc_id: u16 = index_builder::main();
-//     // ! MERGE BATCHES
-//     // ! The following code snippet merges the batches of inverted indexes into a multiple sorted inverted index.
-//     lazy_merger::main(doc_id);
-
-//     println!("Welcome to the Search Engine!");
-//     let mut search_engine = query::SearchEngine::new();
-//     loop {
-//         search_engine.get_query();
-//         search_engine.search();
-//     }
-//     // TODO: Implement search and ranking logic using the query
-// }
+#[derive(Parser)]
+#[command(
+    name = "search_engine",
+    about = "Build, merge and query the inverted index"
+)]
+struct Cli {
+    /// TOML or JSON config file, defaults to $SEARCH_ENGINE_CONFIG
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+// Flags shared by every subcommand, these override the config file and environment
+#[derive(Args)]
+struct LocationArgs {
+    #[arg(long)]
+    corpus_dir: Option<PathBuf>,
+    #[arg(long)]
+    index_dir: Option<PathBuf>,
+}
+
+impl LocationArgs {
+    fn apply(self, config: &mut IndexConfig) {
+        if let Some(corpus_dir) = self.corpus_dir {
+            config.corpus_dir = corpus_dir;
+        }
+        if let Some(index_dir) = self.index_dir {
+            config.index_dir = index_dir;
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Index the crawled corpus into partial indexes (one per batch)
+    Build {
+        #[command(flatten)]
+        locations: LocationArgs,
+        #[arg(long)]
+        batch_size: Option<u16>,
+        /// Merge the partial indexes once the build is done
+        #[arg(long)]
+        merge: bool,
+    },
+    /// Merge the partial indexes into the merged index and its skip lists
+    Merge {
+        #[command(flatten)]
+        locations: LocationArgs,
+        #[arg(long)]
+        batch_size: Option<u16>,
+    },
+    /// Serve the search API over HTTP
+    Serve {
+        #[command(flatten)]
+        locations: LocationArgs,
+        #[arg(long)]
+        host: Option<String>,
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Run a single query, or read queries from stdin when none is given
+    Query {
+        #[command(flatten)]
+        locations: LocationArgs,
+        /// Defaults to the ranking in the config
+        #[arg(long, value_enum)]
+        ranking: Option<scorer::Ranking>,
+        /// Re-rank the best results by HITS authority, needs the page_rank binary to have run
+        #[arg(long)]
+        hits: bool,
+        query: Vec<String>,
+    },
+    /// Rewrite the merged postings in another codec without rebuilding the index
+    Convert {
+        #[command(flatten)]
+        locations: LocationArgs,
+        /// Defaults to the postings codec in the config
+        #[arg(long, value_enum)]
+        codec: Option<postings::PostingsCodec>,
+    },
+    /// Report the number of indexed documents, unique terms and index size on disk
+    Stats {
+        #[command(flatten)]
+        locations: LocationArgs,
+    },
+}
+
+fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let mut config = IndexConfig::load(cli.config.as_deref())?;
+    match cli.command {
+        Command::Build {
+            locations,
+            batch_size,
+            merge,
+        } => {
+            locations.apply(&mut config);
+            config.batch_size = batch_size.unwrap_or(config.batch_size);
+            config.validate()?;
+            let doc_id = index_builder::main(&config)?;
+            if merge {
+                if doc_id == 0 {
+                    println!("No documents found in {}", config.corpus_dir.display());
+                } else {
+                    lazy_merger::main(&config, doc_id);
+                }
+            }
+        }
+        Command::Merge {
+            locations,
+            batch_size,
+        } => {
+            locations.apply(&mut config);
+            config.batch_size = batch_size.unwrap_or(config.batch_size);
+            config.validate()?;
+            config.check_format_version()?;
+            let doc_id = id_book::IDBookElement::document_count(&config);
+            if doc_id == 0 {
+                println!("No documents found in {}", config.index_dir.display());
+            } else {
+                lazy_merger::main(&config, doc_id);
+            }
+        }
+        Command::Serve {
+            locations,
+            host,
+            port,
+        } => {
+            locations.apply(&mut config);
+            config.host = host.unwrap_or(config.host);
+            config.port = port.unwrap_or(config.port);
+            actix_web::rt::System::new().block_on(serve(config))?
+        }
+        Command::Query {
+            locations,
+            ranking,
+            hits,
+            query,
+        } => {
+            locations.apply(&mut config);
+            println!("Welcome to the Search Engine!");
+            let mut search_engine = query::SearchEngine::new(config)?;
+            if let Some(ranking) = ranking {
+                search_engine.set_ranking(ranking, None);
+            }
+            if hits {
+                search_engine.set_hits(true);
+            }
+            if query.is_empty() {
+                // until stdin is closed (Ctrl-D or the end of a piped file)
+                while search_engine.get_query()? {
+                    search_engine.search();
+                }
+            } else {
+                search_engine.set_query(query.join(" "));
+                search_engine.search();
+            }
+        }
+        Command::Convert { locations, codec } => {
+            locations.apply(&mut config);
+            config.check_format_version()?;
+            lazy_merger::convert(&config, codec.unwrap_or(config.postings_codec))?
+        }
+        Command::Stats { locations } => {
+            locations.apply(&mut config);
+            config.check_format_version()?;
+            print_stats(&config)?
+        }
+    }
+    Ok(())
+}
+
+fn print_stats(config: &IndexConfig) -> std::io::Result<()> {
+    let metadata = index_metadata::IndexMetadata::load(config)?;
+    println!("Number of indexed documents: {}", metadata.document_count);
+    println!("Number of unique terms: {}", metadata.unique_term_count);
+    println!(
+        "Average document length: {:.2} tokens",
+        metadata.average_document_length
+    );
+    println!("Built at: {} (unix seconds)", metadata.build_timestamp);
+    println!("Longest indexed n-gram: {}", metadata.max_ngram);
+    println!("Postings codec: {:?}", metadata.postings_codec);
+    println!(
+        "Index size on disk: {} KB",
+        directory_size(&config.merged_dir())? as f64 / 1024.0
+    );
+    Ok(())
+}
+
+fn directory_size(dir: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            size += directory_size(&entry.path())?;
+        } else {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
 
 #[derive(Deserialize)]
 struct SearchRequest {
     query: String,
+    // defaults to the ranking in the config
+    ranking: Option<scorer::Ranking>,
+    // components for the linear ranking, defaults to the ones in the config
+    linear: Option<Vec<scorer::WeightedRanking>>,
+    // HITS re-ranking, defaults to the config
+    hits: Option<bool>,
 }
 
 #[derive(Serialize)]
 struct SearchResult {
     url: String,
-    content: String,
+    title: String,
+    // short passages around the query terms, HTML-escaped with the terms in <mark></mark>
+    snippets: Vec<String>,
+    // query terms the page doesn't contain, for partial matches
+    missing_terms: Vec<String>,
+    // duplicates and same-host pages left out in favour of this one
+    folded: usize,
 }
 
 #[derive(Serialize)]
@@ -45,11 +241,11 @@ struct SearchResponse {
     time: u128,
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn serve(config: IndexConfig) -> std::io::Result<()> {
     println!("Welcome to the Search Engine!");
 
-    let search_engine = Arc::new(Mutex::new(query::SearchEngine::new()));
+    let bind = (config.host.clone(), config.port);
+    let search_engine = Arc::new(Mutex::new(query::SearchEngine::new(config)?));
 
     HttpServer::new(move || {
         // Configure CORS middleware
@@ -65,7 +261,7 @@ async fn main() -> std::io::Result<()> {
             .route("/", web::get().to(index))
             .route("/search", web::post().to(handle_search))
     })
-    .bind(("127.0.0.1", 3000))?
+    .bind(bind)?
     .run()
     .await
 }
@@ -81,21 +277,28 @@ async fn handle_search(
     let mut engine = search_engine.lock().unwrap();
 
     // Set the query and perform search
+    let ranking = payload.ranking.unwrap_or(engine.config().ranking);
+    engine.set_ranking(ranking, payload.linear.as_deref());
+    let hits = payload.hits.unwrap_or(engine.config().hits);
+    engine.set_hits(hits);
     engine.set_query(payload.query.clone());
     let (results, time) = engine.search();
 
     // Limit to 5 results
     let limited_results = results
         .into_iter()
-        .map(|x| SearchResult {
-            url: x.0,
-            content: x.1,
+        .map(|hit| SearchResult {
+            url: hit.url,
+            title: hit.title,
+            snippets: hit.snippets,
+            missing_terms: hit.missing_terms,
+            folded: hit.folded,
         })
         .collect();
 
     HttpResponse::Ok().json(SearchResponse {
         results: limited_results,
-        time: time,
+        time,
     })
 }
 
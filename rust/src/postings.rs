@@ -1,16 +1,45 @@
-use crate::single_posting::Posting;
+use crate::id_book::DocumentLengths;
+use crate::single_posting::{DocId, Field, FieldFreqs, Posting, TermFreq, FIELD_COUNT};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+// How the merged index stores its postings, recorded in the index metadata. The partial indexes
+// of the batches are always text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum PostingsCodec {
+    // one `term:doc|tf|gaps[|fields],...` line per term
+    Text,
+    // binary records, every integer a variable-byte number of 7-bit groups
+    #[default]
+    Varbyte,
+    // binary records, every integer Elias-gamma coded, smaller for the many small gaps
+    Gamma,
+    // binary records, every integer Elias-delta coded, smaller than gamma for large gaps
+    Delta,
+    // binary records in blocks of BLOCK_SIZE postings with their doc id gaps and term frequencies
+    // bit-packed, and the largest doc id of every block up front so whole blocks can be skipped
+    Block,
+}
+
+impl PostingsCodec {
+    pub fn extension(self) -> &'static str {
+        match self {
+            PostingsCodec::Text => "txt",
+            _ => "bin",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Postings {
     pub word: String,
     pub postings: Vec<Posting>,
-    pub skip_list: Vec<SkipList>,
-}
-
-#[derive(Debug, Clone)]
-pub struct SkipList {
-    doc_id: u16,
-    index: u16,
 }
 
 impl Postings {
@@ -18,7 +47,6 @@ impl Postings {
         Postings {
             word: term,
             postings: Vec::new(),
-            skip_list: Vec::new(),
         }
     }
 
@@ -32,14 +60,35 @@ impl Postings {
         self.postings.insert(insert_pos, posting);
     }
 
-    pub fn update_frequency(&mut self, doc_id: u16) {
+    pub fn add_occurrence(&mut self, doc_id: DocId, position: u32) {
         let pos = self.postings.binary_search_by_key(&doc_id, |p| p.doc_id);
         match pos {
             Ok(pos) => {
-                self.postings[pos].increment_freq();
+                self.postings[pos].add_position(position);
+            }
+            Err(_) => {
+                self.push(Posting::new(doc_id, 1, vec![position]));
             }
+        }
+    }
+
+    // an occurrence whose position is never needed, like an n-gram's, only counts towards tf
+    pub fn add_count(&mut self, doc_id: DocId) {
+        match self.postings.binary_search_by_key(&doc_id, |p| p.doc_id) {
+            Ok(pos) => self.postings[pos].term_freq += 1,
+            Err(_) => self.push(Posting::new(doc_id, 1, Vec::new())),
+        }
+    }
+
+    // a term can appear only in a field such as the title, its posting then has no body positions
+    pub fn add_field_occurrence(&mut self, doc_id: DocId, field: Field) {
+        let pos = self.postings.binary_search_by_key(&doc_id, |p| p.doc_id);
+        match pos {
+            Ok(pos) => self.postings[pos].add_field_occurrence(field),
             Err(_) => {
-                self.push(Posting::new(doc_id, 1));
+                let mut posting = Posting::new(doc_id, 0, Vec::new());
+                posting.add_field_occurrence(field);
+                self.push(posting);
             }
         }
     }
@@ -54,35 +103,109 @@ impl Postings {
         }
         let (word, postings_str) = line.split_once(':').unwrap();
         let mut postings = Postings::new(word.to_string());
+        // each posting is doc_id|term_freq|positions, followed by |field_freqs when any is set
         for single_posting in postings_str.split(",") {
-            let (doc_id, term_frequency) = single_posting.split_once("|").unwrap();
-            postings.push(Posting::new(
-                doc_id.trim().parse::<u16>().unwrap(),
-                term_frequency.trim().parse::<u16>().unwrap(),
-            ));
+            let mut parts = single_posting.splitn(4, '|');
+            let doc_id = parts.next().unwrap().trim().parse::<DocId>().unwrap();
+            let term_frequency = parts.next().unwrap().trim().parse::<TermFreq>().unwrap();
+            let positions = decode_positions(parts.next().unwrap_or(""));
+            let mut posting = Posting::new(doc_id, term_frequency, positions);
+            if let Some(fields) = parts.next() {
+                posting.fields = decode_fields(fields);
+            }
+            postings.push(posting);
         }
-        return Ok(postings);
+        Ok(postings)
     }
 
+    // Merges two doc id ordered lists in one pass, the anchor text batch interleaves with every
+    // body batch
     pub fn merge(&mut self, other: Postings) {
         if self.word != other.word {
             panic!("Merging two different terms");
         }
-        let mut i = 0;
-        let mut j = 0;
-        while i < self.postings.len() && j < other.postings.len() {
-            if self.postings[i].doc_id < other.postings[j].doc_id {
-                i += 1;
-            } else if self.postings[i].doc_id > other.postings[j].doc_id {
-                self.postings.insert(i, other.postings[j].clone());
-                i += 1;
-                j += 1;
+        let mut merged = Vec::with_capacity(self.postings.len() + other.postings.len());
+        let mut mine = std::mem::take(&mut self.postings).into_iter().peekable();
+        let mut theirs = other.postings.into_iter().peekable();
+        loop {
+            let next = match (mine.peek(), theirs.peek()) {
+                (Some(a), Some(b)) if a.doc_id < b.doc_id => mine.next(),
+                (Some(a), Some(b)) if a.doc_id > b.doc_id => theirs.next(),
+                (Some(_), Some(_)) => {
+                    // the anchor text batch adds to documents already indexed by a body batch
+                    let mut posting = mine.next().unwrap();
+                    posting.combine(&theirs.next().unwrap());
+                    Some(posting)
+                }
+                (Some(_), None) => mine.next(),
+                (None, Some(_)) => theirs.next(),
+                (None, None) => break,
+            };
+            merged.extend(next);
+        }
+        self.postings = merged;
+    }
+
+    // Binary payload of a term: the posting count, the score bound and the skip pointers as
+    // varbyte, then per posting the doc id gap, the term frequency, the position count (doubled,
+    // plus 1 when field frequencies follow), the position gaps and the field frequencies. The
+    // document lengths go into the score bounds
+    pub fn encode(&self, codec: PostingsCodec, lengths: &DocumentLengths) -> Vec<u8> {
+        if codec == PostingsCodec::Block {
+            return encode_blocks(&self.postings, lengths);
+        }
+        let mut writer = IntWriter::new(codec);
+        let mut skips = Vec::new();
+        let mut previous_doc_id = 0;
+        for (index, posting) in self.postings.iter().enumerate() {
+            if index > 0 && index.is_multiple_of(SKIP_INTERVAL) {
+                skips.push((previous_doc_id, writer.position()));
+            }
+            writer.put(posting.doc_id - previous_doc_id);
+            previous_doc_id = posting.doc_id;
+            writer.put(posting.term_freq);
+            put_details(&mut writer, posting);
+        }
+        let mut payload = Vec::new();
+        put_varbyte(&mut payload, self.postings.len() as u64);
+        ScoreBound::of(&self.postings, lengths).put(&mut payload);
+        put_varbyte(&mut payload, skips.len() as u64);
+        let mut previous = (0, 0);
+        for &(doc_id, position) in &skips {
+            put_varbyte(&mut payload, (doc_id - previous.0) as u64);
+            put_varbyte(&mut payload, (position - previous.1) as u64);
+            previous = (doc_id, position);
+        }
+        payload.extend(writer.finish());
+        payload
+    }
+
+    pub fn decode(word: String, payload: &[u8], codec: PostingsCodec) -> io::Result<Postings> {
+        let mut postings = Postings::new(word);
+        if codec == PostingsCodec::Block {
+            let blocks = PostingBlocks::parse(payload)?;
+            postings.postings.reserve(blocks.posting_count());
+            for block in 0..blocks.block_count() {
+                let decoded = blocks.decode_block(payload, block)?;
+                for index in 0..decoded.len() {
+                    postings.postings.push(decoded.posting(payload, index)?);
+                }
             }
+            return Ok(postings);
         }
-        while j < other.postings.len() {
-            self.postings.push(other.postings[j].clone());
-            j += 1;
+        let header = StreamHeader::parse(payload)?;
+        let mut reader = IntReader::new(codec, &payload[header.body..]);
+        postings.postings.reserve(header.count);
+        let mut doc_id = 0;
+        for _ in 0..header.count {
+            doc_id += reader.get()?;
+            let term_freq = reader.get()?;
+            // doc ids only grow, so there is no need to search for the insert position
+            postings
+                .postings
+                .push(get_details(&mut reader, doc_id, term_freq)?);
         }
+        Ok(postings)
     }
 
     pub fn save_postings(&self) -> String {
@@ -90,46 +213,1284 @@ impl Postings {
         result.push_str(&self.word);
         result.push(':');
         for posting in &self.postings {
-            result.push_str(&format!("{}|{},", posting.doc_id, posting.term_freq));
+            result.push_str(&format!(
+                "{}|{}|{}",
+                posting.doc_id,
+                posting.term_freq,
+                encode_positions(&posting.positions)
+            ));
+            if posting.fields.iter().any(|&freq| freq > 0) {
+                result.push('|');
+                result.push_str(&encode_fields(&posting.fields));
+            }
+            result.push(',');
         }
         result.pop();
         result
     }
+}
+
+// positions are written as gaps from the previous position, separated by ';'
+pub fn encode_positions(positions: &[u32]) -> String {
+    let mut previous = 0;
+    positions
+        .iter()
+        .map(|&position| {
+            let gap = position - previous;
+            previous = position;
+            gap.to_string()
+        })
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+pub fn decode_positions(encoded: &str) -> Vec<u32> {
+    let mut position = 0;
+    encoded
+        .trim()
+        .split(';')
+        .filter(|gap| !gap.is_empty())
+        .map(|gap| {
+            position += gap.parse::<u32>().unwrap();
+            position
+        })
+        .collect()
+}
+
+// field frequencies in `Field` order, separated by ';'
+pub fn encode_fields(fields: &FieldFreqs) -> String {
+    fields
+        .iter()
+        .map(|freq| freq.to_string())
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+pub fn decode_fields(encoded: &str) -> FieldFreqs {
+    let mut fields = [0; FIELD_COUNT];
+    for (field, freq) in fields.iter_mut().zip(encoded.trim().split(';')) {
+        *field = freq.parse::<TermFreq>().unwrap();
+    }
+    fields
+}
+
+// Reads the terms of a merged file one after another, in the codec it was written with
+pub struct PostingsReader {
+    reader: BufReader<File>,
+    codec: PostingsCodec,
+}
+
+impl PostingsReader {
+    pub fn open(path: &Path, codec: PostingsCodec) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(File::open(path)?),
+            codec,
+        })
+    }
 
-    // ? Unused for now since if you are going to load the entire postlist into memory, you dont need skip list
-    pub fn build_skip_list(&mut self) {
-        let mut skip_list = Vec::with_capacity(5);
-        let postings_length = self.postings.len();
-        // Create a skip list with 5 entries
-        if postings_length >= 5 {
-            let step = postings_length / 5;
-            for i in 0..5 {
-                skip_list.push(SkipList {
-                    doc_id: self.postings[i * step].doc_id,
-                    index: (i * step) as u16,
-                });
+    pub fn next_postings(&mut self) -> io::Result<Option<Postings>> {
+        if self.codec == PostingsCodec::Text {
+            let mut line = String::new();
+            while self.reader.read_line(&mut line)? > 0 {
+                if let Ok(postings) = Postings::load_postings(line.trim_end_matches('\n')) {
+                    return Ok(Some(postings));
+                }
+                line.clear();
             }
-        } else {
-            // If less than 5 elements, just add what we have
-            for i in 0..postings_length {
-                skip_list.push(SkipList {
-                    doc_id: self.postings[i].doc_id,
-                    index: i as u16,
-                });
+            return Ok(None);
+        }
+        match read_record(&mut self.reader)? {
+            Some((word, payload)) => Postings::decode(word, &payload, self.codec).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// Writes the terms of a merged file in order
+pub struct PostingsWriter {
+    writer: BufWriter<File>,
+    codec: PostingsCodec,
+    // for the score bounds of the binary codecs
+    lengths: Arc<DocumentLengths>,
+}
+
+impl PostingsWriter {
+    pub fn create(
+        path: &Path,
+        codec: PostingsCodec,
+        lengths: Arc<DocumentLengths>,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            codec,
+            lengths,
+        })
+    }
+
+    pub fn write(&mut self, postings: &Postings) -> io::Result<()> {
+        if self.codec == PostingsCodec::Text {
+            return self
+                .writer
+                .write_all((postings.save_postings() + "\n").as_bytes());
+        }
+        write_record(
+            &mut self.writer,
+            &postings.word,
+            &postings.encode(self.codec, &self.lengths),
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// A binary merged file is a sequence of records: the term's length and bytes, then the payload's
+// length and bytes, the lengths always as varbyte so records can be stepped over without decoding
+pub fn write_record(writer: &mut impl Write, word: &str, payload: &[u8]) -> io::Result<()> {
+    let mut header = Vec::new();
+    put_varbyte(&mut header, word.len() as u64);
+    header.extend_from_slice(word.as_bytes());
+    put_varbyte(&mut header, payload.len() as u64);
+    writer.write_all(&header)?;
+    writer.write_all(payload)
+}
+
+// (term, payload) of the next record, None at the end of the file
+pub fn read_record(reader: &mut impl Read) -> io::Result<Option<(String, Vec<u8>)>> {
+    let word_length = match read_varbyte(reader) {
+        Ok(length) => length,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut word = vec![0; word_length as usize];
+    reader.read_exact(&mut word)?;
+    let word =
+        String::from_utf8(word).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut payload = vec![0; read_varbyte(reader)? as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((word, payload)))
+}
+
+// 7 bits per byte, lowest group first, the high bit set on every byte but the last
+fn put_varbyte(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varbyte(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    let mut shift = 0;
+    let mut byte = [0];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if shift > 63 {
+            return Err(truncated());
+        }
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt postings record")
+}
+
+// the position count (doubled, plus 1 when field frequencies follow), the position gaps and the
+// field frequencies of a posting
+fn put_details(writer: &mut IntWriter, posting: &Posting) {
+    let has_fields = posting.fields.iter().any(|&freq| freq > 0);
+    writer.put((posting.positions.len() as u32) << 1 | has_fields as u32);
+    let mut previous_position = 0;
+    for &position in &posting.positions {
+        writer.put(position - previous_position);
+        previous_position = position;
+    }
+    if has_fields {
+        for &freq in &posting.fields {
+            writer.put(freq);
+        }
+    }
+}
+
+fn get_details(reader: &mut IntReader, doc_id: DocId, term_freq: TermFreq) -> io::Result<Posting> {
+    let header = reader.get()?;
+    let mut positions = Vec::with_capacity((header >> 1) as usize);
+    let mut position = 0;
+    for _ in 0..header >> 1 {
+        position += reader.get()?;
+        positions.push(position);
+    }
+    let mut posting = Posting::new(doc_id, term_freq, positions);
+    if header & 1 == 1 {
+        for freq in posting.fields.iter_mut() {
+            *freq = reader.get()?;
+        }
+    }
+    Ok(posting)
+}
+
+// The largest term frequency, the largest frequency in each field and the shortest document
+// over some postings, not necessarily all from the same one. Stored with the postings so a scorer
+// can bound what any of them scores without decoding them.
+// The bounds of a list sit in its payload header rather than in the term dictionary: the skip
+// lists only keep every 100th term in memory, and the header comes in the same read as the
+// postings a query fetches anyway
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScoreBound {
+    pub term_freq: TermFreq,
+    pub fields: FieldFreqs,
+    // in tokens, 0 when there are no postings
+    pub min_document_length: u32,
+}
+
+impl ScoreBound {
+    pub fn of(postings: &[Posting], lengths: &DocumentLengths) -> Self {
+        let mut bound = Self {
+            min_document_length: if postings.is_empty() { 0 } else { u32::MAX },
+            ..Self::default()
+        };
+        for posting in postings {
+            bound.term_freq = bound.term_freq.max(posting.term_freq);
+            for (max, &freq) in bound.fields.iter_mut().zip(&posting.fields) {
+                *max = (*max).max(freq);
             }
+            bound.min_document_length = bound.min_document_length.min(lengths.get(posting.doc_id));
+        }
+        bound
+    }
+
+    fn put(&self, bytes: &mut Vec<u8>) {
+        put_varbyte(bytes, self.term_freq as u64);
+        for &freq in &self.fields {
+            put_varbyte(bytes, freq as u64);
+        }
+        put_varbyte(bytes, self.min_document_length as u64);
+    }
+
+    fn read(bytes: &mut &[u8]) -> io::Result<Self> {
+        let mut get = || u32::try_from(read_varbyte(bytes)?).map_err(|_| truncated());
+        let term_freq = get()?;
+        let mut fields = [0; FIELD_COUNT];
+        for freq in fields.iter_mut() {
+            *freq = get()?;
         }
-        self.skip_list = skip_list;
+        Ok(Self {
+            term_freq,
+            fields,
+            min_document_length: get()?,
+        })
     }
+}
+
+// postings per block of the block codec
+pub const BLOCK_SIZE: usize = 128;
+// exceptions of a packed block store their index in a single byte
+const _: () = assert!(BLOCK_SIZE <= 256);
+// the bit width of a packed block is the smallest that fits this share of its values, the rest
+// are stored as exceptions
+const PACKED_SHARE: f64 = 0.9;
 
-    // ? Unused for now since if you are going to load the entire postlist into memory, you dont need skip list
-    pub fn skip_list_to_file(&self) -> String {
-        let result = self
-            .skip_list
+// Block payload: the posting count and the score bound of the list, then per block the gap
+// between its largest doc id and the previous block's, the byte length of the block and its own
+// score bound, then the blocks. A block holds its doc id gaps, term frequencies and the byte
+// length of each posting's details bit-packed, then the details of each posting as varbyte
+fn encode_blocks(postings: &[Posting], lengths: &DocumentLengths) -> Vec<u8> {
+    let mut directory = Vec::new();
+    let mut data = Vec::new();
+    put_varbyte(&mut directory, postings.len() as u64);
+    ScoreBound::of(postings, lengths).put(&mut directory);
+    let mut previous_max = 0;
+    for block in postings.chunks(BLOCK_SIZE) {
+        let block_start = data.len();
+        let mut previous_doc_id = previous_max;
+        let gaps: Vec<u32> = block
+            .iter()
+            .map(|posting| {
+                let gap = posting.doc_id - previous_doc_id;
+                previous_doc_id = posting.doc_id;
+                gap
+            })
+            .collect();
+        let term_freqs: Vec<u32> = block.iter().map(|posting| posting.term_freq).collect();
+        let mut details = IntWriter::new(PostingsCodec::Varbyte);
+        let detail_lengths: Vec<u32> = block
             .iter()
-            .map(|x| format!("{}|{}", x.doc_id, x.index))
-            .collect::<Vec<String>>()
-            .join(",");
+            .map(|posting| {
+                let start = details.position();
+                put_details(&mut details, posting);
+                (details.position() - start) as u32
+            })
+            .collect();
+        put_packed(&mut data, &gaps);
+        put_packed(&mut data, &term_freqs);
+        put_packed(&mut data, &detail_lengths);
+        data.extend(details.finish());
 
-        result
+        let max_doc_id = block.last().unwrap().doc_id;
+        put_varbyte(&mut directory, (max_doc_id - previous_max) as u64);
+        put_varbyte(&mut directory, (data.len() - block_start) as u64);
+        ScoreBound::of(block, lengths).put(&mut directory);
+        previous_max = max_doc_id;
+    }
+    directory.extend(data);
+    directory
+}
+
+// Patched frame of reference: the bit width, the exception count, every value's low bits packed
+// least significant bit first, then the (index, high bits) of each value too wide for them
+fn put_packed(bytes: &mut Vec<u8>, values: &[u32]) {
+    let mut widths: Vec<u32> = values
+        .iter()
+        .map(|value| 32 - value.leading_zeros())
+        .collect();
+    widths.sort_unstable();
+    let fitted = ((values.len() as f64 * PACKED_SHARE).ceil() as usize).max(1);
+    let width = widths[fitted - 1];
+    let mask = (1u64 << width) - 1;
+    let exceptions: Vec<(usize, u64)> = values
+        .iter()
+        .enumerate()
+        .filter(|(_, &value)| value as u64 > mask)
+        .map(|(index, &value)| (index, value as u64 >> width))
+        .collect();
+    bytes.push(width as u8);
+    put_varbyte(bytes, exceptions.len() as u64);
+
+    let mut buffer: u64 = 0;
+    let mut buffered = 0;
+    for &value in values {
+        buffer |= (value as u64 & mask) << buffered;
+        buffered += width;
+        while buffered >= 8 {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            buffered -= 8;
+        }
+    }
+    if buffered > 0 {
+        bytes.push(buffer as u8);
+    }
+    for (index, high) in exceptions {
+        bytes.push(index as u8);
+        put_varbyte(bytes, high);
+    }
+}
+
+fn get_packed(bytes: &mut &[u8], count: usize) -> io::Result<Vec<u32>> {
+    let (&width, rest) = bytes.split_first().ok_or_else(truncated)?;
+    *bytes = rest;
+    let width = width as u32;
+    if width > 32 {
+        return Err(truncated());
+    }
+    let exception_count = read_varbyte(bytes)?;
+    let packed_length = (count * width as usize).div_ceil(8);
+    if bytes.len() < packed_length {
+        return Err(truncated());
+    }
+    let (packed, rest) = bytes.split_at(packed_length);
+    *bytes = rest;
+
+    // every value lies within the 8 bytes from the one holding its first bit, so it takes one
+    // unaligned load and no state carried over from the value before it
+    let mask = (1u64 << width) - 1;
+    let unpack = |bit: usize| {
+        let start = bit / 8;
+        let word = match packed.get(start..start + 8) {
+            Some(word) => u64::from_le_bytes(word.try_into().unwrap()),
+            None => {
+                let mut word = [0; 8];
+                word[..packed.len() - start].copy_from_slice(&packed[start..]);
+                u64::from_le_bytes(word)
+            }
+        };
+        ((word >> (bit % 8)) & mask) as u32
+    };
+    let mut values: Vec<u32> = (0..count)
+        .map(|index| unpack(index * width as usize))
+        .collect();
+    for _ in 0..exception_count {
+        let (&index, rest) = bytes.split_first().ok_or_else(truncated)?;
+        *bytes = rest;
+        let high = read_varbyte(bytes)?;
+        let value = values.get_mut(index as usize).ok_or_else(truncated)?;
+        *value = u32::try_from((high << width) | *value as u64).map_err(|_| truncated())?;
+    }
+    Ok(values)
+}
+
+// The block directory of a Block payload, so a reader can find the blocks that may hold a doc id
+// and decode only those
+#[derive(Debug)]
+pub struct PostingBlocks {
+    bound: ScoreBound,
+    blocks: Vec<BlockEntry>,
+}
+
+#[derive(Debug)]
+struct BlockEntry {
+    max_doc_id: DocId,
+    previous_max: DocId,
+    count: usize,
+    // bytes of the block in the payload
+    range: Range<usize>,
+    bound: ScoreBound,
+}
+
+impl PostingBlocks {
+    pub fn parse(payload: &[u8]) -> io::Result<Self> {
+        let mut bytes = payload;
+        let count = read_varbyte(&mut bytes)? as usize;
+        let bound = ScoreBound::read(&mut bytes)?;
+        let block_count = count.div_ceil(BLOCK_SIZE);
+        let mut directory = Vec::with_capacity(block_count);
+        let mut max_doc_id: DocId = 0;
+        for _ in 0..block_count {
+            let gap = u32::try_from(read_varbyte(&mut bytes)?).map_err(|_| truncated())?;
+            max_doc_id = max_doc_id.checked_add(gap).ok_or_else(truncated)?;
+            let length = read_varbyte(&mut bytes)? as usize;
+            directory.push((max_doc_id, length, ScoreBound::read(&mut bytes)?));
+        }
+        let mut blocks = Vec::with_capacity(block_count);
+        let mut start = payload.len() - bytes.len();
+        let mut previous_max = 0;
+        for (index, (max_doc_id, length, block_bound)) in directory.into_iter().enumerate() {
+            if payload.len() < start + length {
+                return Err(truncated());
+            }
+            blocks.push(BlockEntry {
+                max_doc_id,
+                previous_max,
+                count: (count - index * BLOCK_SIZE).min(BLOCK_SIZE),
+                range: start..start + length,
+                bound: block_bound,
+            });
+            start += length;
+            previous_max = max_doc_id;
+        }
+        Ok(Self { bound, blocks })
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn posting_count(&self) -> usize {
+        self.blocks.iter().map(|block| block.count).sum()
+    }
+
+    pub fn max_doc_id(&self, block: usize) -> DocId {
+        self.blocks[block].max_doc_id
+    }
+
+    // bound of the whole list
+    pub fn bound(&self) -> ScoreBound {
+        self.bound
+    }
+
+    pub fn block_bound(&self, block: usize) -> ScoreBound {
+        self.blocks[block].bound
+    }
+
+    // first block from `from` on whose largest doc id is at least target, block_count if none
+    pub fn find_block(&self, from: usize, target: DocId) -> usize {
+        from + self.blocks[from..].partition_point(|block| block.max_doc_id < target)
+    }
+
+    // unpacks the doc ids and term frequencies of a block, its details are left in the payload
+    pub fn decode_block(&self, payload: &[u8], block: usize) -> io::Result<DecodedBlock> {
+        let entry = &self.blocks[block];
+        let mut bytes = &payload[entry.range.clone()];
+        let gaps = get_packed(&mut bytes, entry.count)?;
+        let term_freqs = get_packed(&mut bytes, entry.count)?;
+        let detail_lengths = get_packed(&mut bytes, entry.count)?;
+        let mut doc_id = entry.previous_max;
+        let doc_ids = gaps
+            .into_iter()
+            .map(|gap| {
+                doc_id += gap;
+                doc_id
+            })
+            .collect();
+        let mut offset = entry.range.end - bytes.len();
+        let mut detail_offsets = Vec::with_capacity(entry.count + 1);
+        detail_offsets.push(offset);
+        for length in detail_lengths {
+            offset += length as usize;
+            detail_offsets.push(offset);
+        }
+        if offset != entry.range.end {
+            return Err(truncated());
+        }
+        Ok(DecodedBlock {
+            doc_ids,
+            term_freqs,
+            detail_offsets,
+        })
+    }
+
+    // postings of the given documents (sorted), decoding only the blocks whose doc id range
+    // holds one of them and the details of the postings found
+    pub fn postings_of(&self, payload: &[u8], doc_ids: &[DocId]) -> io::Result<Vec<Posting>> {
+        let mut postings = Vec::new();
+        let mut remaining = doc_ids;
+        for (block, entry) in self.blocks.iter().enumerate() {
+            let start = remaining.partition_point(|&doc_id| doc_id <= entry.previous_max);
+            remaining = &remaining[start..];
+            if remaining.is_empty() {
+                break;
+            }
+            if remaining[0] > entry.max_doc_id {
+                continue;
+            }
+            let decoded = self.decode_block(payload, block)?;
+            for (index, doc_id) in decoded.doc_ids.iter().enumerate() {
+                if remaining.binary_search(doc_id).is_ok() {
+                    postings.push(decoded.posting(payload, index)?);
+                }
+            }
+        }
+        Ok(postings)
+    }
+}
+
+// A block of the block codec with its doc ids and term frequencies unpacked. The positions and
+// field frequencies of a posting are only decoded when posting() asks for it
+#[derive(Debug, Default)]
+pub struct DecodedBlock {
+    doc_ids: Vec<DocId>,
+    term_freqs: Vec<TermFreq>,
+    // where the details of each posting start in the payload, then where the last one ends
+    detail_offsets: Vec<usize>,
+}
+
+impl DecodedBlock {
+    pub fn len(&self) -> usize {
+        self.doc_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.doc_ids.is_empty()
+    }
+
+    pub fn doc_ids(&self) -> &[DocId] {
+        &self.doc_ids
+    }
+
+    pub fn posting(&self, payload: &[u8], index: usize) -> io::Result<Posting> {
+        let details = &payload[self.detail_offsets[index]..self.detail_offsets[index + 1]];
+        get_details(
+            &mut IntReader::new(PostingsCodec::Varbyte, details),
+            self.doc_ids[index],
+            self.term_freqs[index],
+        )
+    }
+}
+
+// postings between two skip pointers of the varbyte, gamma and delta codecs
+pub const SKIP_INTERVAL: usize = 128;
+
+// Start of a varbyte, gamma or delta payload
+#[derive(Debug)]
+struct StreamHeader {
+    count: usize,
+    bound: ScoreBound,
+    // (posting index, doc id of the posting before it, position in the body) of every
+    // SKIP_INTERVAL-th posting
+    skips: Vec<(usize, DocId, usize)>,
+    // where the postings start in the payload
+    body: usize,
+}
+
+impl StreamHeader {
+    fn parse(payload: &[u8]) -> io::Result<Self> {
+        let mut bytes = payload;
+        let count = read_varbyte(&mut bytes)? as usize;
+        let bound = ScoreBound::read(&mut bytes)?;
+        let skip_count = read_varbyte(&mut bytes)? as usize;
+        let mut skips = Vec::with_capacity(skip_count.min(count));
+        let (mut doc_id, mut position): (DocId, usize) = (0, 0);
+        for index in 1..=skip_count {
+            let gap = u32::try_from(read_varbyte(&mut bytes)?).map_err(|_| truncated())?;
+            doc_id = doc_id.checked_add(gap).ok_or_else(truncated)?;
+            position += read_varbyte(&mut bytes)? as usize;
+            skips.push((index * SKIP_INTERVAL, doc_id, position));
+        }
+        Ok(Self {
+            count,
+            bound,
+            skips,
+            body: payload.len() - bytes.len(),
+        })
+    }
+}
+
+// Walks a term's postings in doc id order straight from the merged index. advance() steps over
+// whole blocks or skip intervals that end before its target instead of decoding them
+#[derive(Debug)]
+pub struct PostingCursor {
+    payload: Vec<u8>,
+    source: CursorSource,
+}
+
+#[derive(Debug)]
+enum CursorSource {
+    // varbyte, gamma and delta, `current` is the last posting decoded
+    Stream {
+        codec: PostingsCodec,
+        header: StreamHeader,
+        next_index: usize,
+        position: usize,
+        current: Option<Posting>,
+    },
+    // the current posting is the next-th of the decoded block
+    Blocks {
+        blocks: PostingBlocks,
+        block: usize,
+        decoded: DecodedBlock,
+        next: usize,
+    },
+    // text postings, decoded up front
+    Decoded {
+        postings: Vec<Posting>,
+        next: usize,
+        bound: ScoreBound,
+    },
+}
+
+impl PostingCursor {
+    pub fn new(payload: Vec<u8>, codec: PostingsCodec) -> io::Result<Self> {
+        let source = match codec {
+            PostingsCodec::Text => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "text postings have no binary payload",
+                ))
+            }
+            PostingsCodec::Block => CursorSource::Blocks {
+                blocks: PostingBlocks::parse(&payload)?,
+                block: 0,
+                decoded: DecodedBlock::default(),
+                next: 0,
+            },
+            _ => CursorSource::Stream {
+                codec,
+                header: StreamHeader::parse(&payload)?,
+                next_index: 0,
+                position: 0,
+                current: None,
+            },
+        };
+        let mut cursor = Self { payload, source };
+        cursor.rewind()?;
+        Ok(cursor)
+    }
+
+    pub fn from_postings(postings: Vec<Posting>, lengths: &DocumentLengths) -> Self {
+        Self {
+            payload: Vec::new(),
+            source: CursorSource::Decoded {
+                bound: ScoreBound::of(&postings, lengths),
+                postings,
+                next: 0,
+            },
+        }
+    }
+
+    pub fn document_frequency(&self) -> usize {
+        match &self.source {
+            CursorSource::Stream { header, .. } => header.count,
+            CursorSource::Blocks { blocks, .. } => blocks.posting_count(),
+            CursorSource::Decoded { postings, .. } => postings.len(),
+        }
+    }
+
+    // bound of every posting in the list
+    pub fn score_bound(&self) -> ScoreBound {
+        match &self.source {
+            CursorSource::Stream { header, .. } => header.bound,
+            CursorSource::Blocks { blocks, .. } => blocks.bound(),
+            CursorSource::Decoded { bound, .. } => *bound,
+        }
+    }
+
+    // Bound of the postings from target up to the returned doc id, found without decoding any:
+    // the block that would hold target with the block codec, the rest of the list otherwise.
+    // None when no posting of the block codec is at or past target
+    pub fn block_bound(&self, target: DocId) -> Option<(DocId, ScoreBound)> {
+        match &self.source {
+            CursorSource::Blocks { blocks, block, .. } => {
+                let found = blocks.find_block(*block, target);
+                (found < blocks.block_count())
+                    .then(|| (blocks.max_doc_id(found), blocks.block_bound(found)))
+            }
+            _ => Some((DocId::MAX, self.score_bound())),
+        }
+    }
+
+    // None once every posting has been passed. With the block codec the positions and field
+    // frequencies are decoded here, so postings that are only stepped over never are
+    pub fn posting(&self) -> io::Result<Option<Posting>> {
+        match &self.source {
+            CursorSource::Stream { current, .. } => Ok(current.clone()),
+            CursorSource::Blocks { decoded, next, .. } => (*next < decoded.len())
+                .then(|| decoded.posting(&self.payload, *next))
+                .transpose(),
+            CursorSource::Decoded { postings, next, .. } => Ok(postings.get(*next).cloned()),
+        }
+    }
+
+    pub fn doc_id(&self) -> Option<DocId> {
+        match &self.source {
+            CursorSource::Stream { current, .. } => current.as_ref().map(|posting| posting.doc_id),
+            CursorSource::Blocks { decoded, next, .. } => decoded.doc_ids().get(*next).copied(),
+            CursorSource::Decoded { postings, next, .. } => {
+                postings.get(*next).map(|posting| posting.doc_id)
+            }
+        }
+    }
+
+    // back to the first posting
+    pub fn rewind(&mut self) -> io::Result<()> {
+        match &mut self.source {
+            CursorSource::Stream {
+                next_index,
+                position,
+                current,
+                ..
+            } => {
+                *next_index = 0;
+                *position = 0;
+                *current = None;
+                self.step(0)
+            }
+            CursorSource::Blocks {
+                blocks,
+                block,
+                decoded,
+                next,
+            } => {
+                *block = 0;
+                *next = 0;
+                *decoded = if blocks.block_count() > 0 {
+                    blocks.decode_block(&self.payload, 0)?
+                } else {
+                    DecodedBlock::default()
+                };
+                Ok(())
+            }
+            CursorSource::Decoded { next, .. } => {
+                *next = 0;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn move_next(&mut self) -> io::Result<()> {
+        match &mut self.source {
+            CursorSource::Stream { current, .. } => {
+                let previous_doc_id = current.as_ref().map_or(0, |posting| posting.doc_id);
+                self.step(previous_doc_id)
+            }
+            CursorSource::Blocks {
+                blocks,
+                block,
+                decoded,
+                next,
+            } => {
+                *next += 1;
+                if *next >= decoded.len() && *block < blocks.block_count() {
+                    *block += 1;
+                    *next = 0;
+                    *decoded = if *block < blocks.block_count() {
+                        blocks.decode_block(&self.payload, *block)?
+                    } else {
+                        DecodedBlock::default()
+                    };
+                }
+                Ok(())
+            }
+            CursorSource::Decoded { next, .. } => {
+                *next += 1;
+                Ok(())
+            }
+        }
+    }
+
+    // moves to the first posting whose doc id is at least target, never backwards
+    pub fn advance(&mut self, target: DocId) -> io::Result<()> {
+        let current_doc_id = match self.doc_id() {
+            Some(doc_id) if doc_id < target => doc_id,
+            _ => return Ok(()),
+        };
+        match &mut self.source {
+            CursorSource::Stream {
+                header,
+                next_index,
+                position,
+                ..
+            } => {
+                // the last skip pointer before the target, if it is ahead of the cursor
+                let skip = header.skips.partition_point(|skip| skip.1 < target);
+                let mut previous_doc_id = current_doc_id;
+                if skip > 0 && header.skips[skip - 1].0 >= *next_index {
+                    let (index, doc_id, skip_position) = header.skips[skip - 1];
+                    *next_index = index;
+                    *position = skip_position;
+                    previous_doc_id = doc_id;
+                }
+                self.step(previous_doc_id)?;
+                while self.doc_id().is_some_and(|doc_id| doc_id < target) {
+                    self.move_next()?;
+                }
+                Ok(())
+            }
+            CursorSource::Blocks {
+                blocks,
+                block,
+                decoded,
+                next,
+            } => {
+                if blocks.max_doc_id(*block) < target {
+                    *block = blocks.find_block(*block + 1, target);
+                    *next = 0;
+                    *decoded = if *block < blocks.block_count() {
+                        blocks.decode_block(&self.payload, *block)?
+                    } else {
+                        DecodedBlock::default()
+                    };
+                }
+                *next = gallop(decoded.doc_ids(), *next, target, |&doc_id| doc_id);
+                Ok(())
+            }
+            CursorSource::Decoded { postings, next, .. } => {
+                *next = gallop(postings, *next, target, |posting| posting.doc_id);
+                Ok(())
+            }
+        }
+    }
+
+    // decodes the posting at next_index of a stream, whose previous doc id is given
+    fn step(&mut self, previous_doc_id: DocId) -> io::Result<()> {
+        let CursorSource::Stream {
+            codec,
+            header,
+            next_index,
+            position,
+            current,
+        } = &mut self.source
+        else {
+            return Ok(());
+        };
+        if *next_index >= header.count {
+            *current = None;
+            return Ok(());
+        }
+        let mut reader = IntReader::at(*codec, &self.payload[header.body..], *position);
+        let doc_id = previous_doc_id + reader.get()?;
+        let term_freq = reader.get()?;
+        *current = Some(get_details(&mut reader, doc_id, term_freq)?);
+        *position = reader.position();
+        *next_index += 1;
+        Ok(())
+    }
+}
+
+// index of the first item from start on whose doc id is at least target, found by doubling the
+// step and then binary searching the last one
+fn gallop<T>(items: &[T], start: usize, target: DocId, doc_id: impl Fn(&T) -> DocId) -> usize {
+    let mut low = start;
+    let mut high = start;
+    let mut step = 1;
+    while high < items.len() && doc_id(&items[high]) < target {
+        low = high + 1;
+        high = start + step;
+        step *= 2;
+    }
+    let high = high.min(items.len()).max(low);
+    low + items[low..high].partition_point(|item| doc_id(item) < target)
+}
+
+// Integers of a binary payload. Elias codes can't represent 0, so value + 1 is coded instead
+enum IntWriter {
+    Varbyte(Vec<u8>),
+    Gamma(BitWriter),
+    Delta(BitWriter),
+}
+
+impl IntWriter {
+    fn new(codec: PostingsCodec) -> Self {
+        match codec {
+            PostingsCodec::Gamma => IntWriter::Gamma(BitWriter::default()),
+            PostingsCodec::Delta => IntWriter::Delta(BitWriter::default()),
+            _ => IntWriter::Varbyte(Vec::new()),
+        }
+    }
+
+    fn put(&mut self, value: u32) {
+        match self {
+            IntWriter::Varbyte(bytes) => put_varbyte(bytes, value as u64),
+            IntWriter::Gamma(bits) => bits.put_gamma(value as u64 + 1),
+            IntWriter::Delta(bits) => bits.put_delta(value as u64 + 1),
+        }
+    }
+
+    // bytes written for varbyte, bits for the Elias codes
+    fn position(&self) -> usize {
+        match self {
+            IntWriter::Varbyte(bytes) => bytes.len(),
+            IntWriter::Gamma(bits) | IntWriter::Delta(bits) => bits.length,
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            IntWriter::Varbyte(bytes) => bytes,
+            IntWriter::Gamma(bits) | IntWriter::Delta(bits) => bits.bytes,
+        }
+    }
+}
+
+enum IntReader<'a> {
+    Varbyte { bytes: &'a [u8], position: usize },
+    Gamma(BitReader<'a>),
+    Delta(BitReader<'a>),
+}
+
+impl<'a> IntReader<'a> {
+    fn new(codec: PostingsCodec, bytes: &'a [u8]) -> Self {
+        Self::at(codec, bytes, 0)
+    }
+
+    // reading from a position given by IntWriter::position
+    fn at(codec: PostingsCodec, bytes: &'a [u8], position: usize) -> Self {
+        match codec {
+            PostingsCodec::Gamma => IntReader::Gamma(BitReader { bytes, position }),
+            PostingsCodec::Delta => IntReader::Delta(BitReader { bytes, position }),
+            _ => IntReader::Varbyte { bytes, position },
+        }
+    }
+
+    fn position(&self) -> usize {
+        match self {
+            IntReader::Varbyte { position, .. } => *position,
+            IntReader::Gamma(bits) | IntReader::Delta(bits) => bits.position,
+        }
+    }
+
+    fn get(&mut self) -> io::Result<u32> {
+        let value = match self {
+            IntReader::Varbyte { bytes, position } => {
+                let mut rest = bytes.get(*position..).ok_or_else(truncated)?;
+                let value = read_varbyte(&mut rest).map_err(|_| truncated())?;
+                *position = bytes.len() - rest.len();
+                value
+            }
+            IntReader::Gamma(bits) => bits.get_gamma().ok_or_else(truncated)? - 1,
+            IntReader::Delta(bits) => bits.get_delta().ok_or_else(truncated)? - 1,
+        };
+        u32::try_from(value).map_err(|_| truncated())
+    }
+}
+
+// bits are filled from the most significant bit of each byte
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    length: usize,
+}
+
+impl BitWriter {
+    fn put_bits(&mut self, value: u64, count: u32) {
+        for bit in (0..count).rev() {
+            if self.length.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> bit) & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.length % 8);
+            }
+            self.length += 1;
+        }
+    }
+
+    // floor(log2 n) zeros, then n in binary (which starts with a 1)
+    fn put_gamma(&mut self, value: u64) {
+        let length = 63 - value.leading_zeros();
+        self.put_bits(0, length);
+        self.put_bits(value, length + 1);
+    }
+
+    // the bit length of n gamma coded, then n in binary without its leading 1
+    fn put_delta(&mut self, value: u64) {
+        let length = 63 - value.leading_zeros();
+        self.put_gamma(length as u64 + 1);
+        self.put_bits(value, length);
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn get_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.position / 8)?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn get_bits(&mut self, count: u32) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = value << 1 | self.get_bit()? as u64;
+        }
+        Some(value)
+    }
+
+    fn get_gamma(&mut self) -> Option<u64> {
+        let mut length = 0;
+        while !self.get_bit()? {
+            length += 1;
+            if length > 63 {
+                return None;
+            }
+        }
+        Some(1 << length | self.get_bits(length)?)
+    }
+
+    fn get_delta(&mut self) -> Option<u64> {
+        let length = self.get_gamma()? - 1;
+        if length > 63 {
+            return None;
+        }
+        Some(1 << length | self.get_bits(length as u32)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINARY_CODECS: [PostingsCodec; 4] = [
+        PostingsCodec::Varbyte,
+        PostingsCodec::Gamma,
+        PostingsCodec::Delta,
+        PostingsCodec::Block,
+    ];
+
+    // Posting only compares doc ids
+    fn contents(postings: &[Posting]) -> Vec<(DocId, TermFreq, Vec<u32>, FieldFreqs)> {
+        postings
+            .iter()
+            .map(|posting| {
+                (
+                    posting.doc_id,
+                    posting.term_freq,
+                    posting.positions.clone(),
+                    posting.fields,
+                )
+            })
+            .collect()
+    }
+
+    // count postings with pseudo-random gaps below max_gap, a few with field frequencies and
+    // some, like n-grams, with a count but no positions
+    fn sample(count: usize, max_gap: u32, seed: u64) -> Postings {
+        let mut state = seed;
+        let mut next = |bound: u32| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 33) % bound as u64) as u32
+        };
+        let mut postings = Postings::new("term".to_string());
+        let mut doc_id = 0;
+        for _ in 0..count {
+            doc_id += 1 + next(max_gap);
+            let mut posting = if next(4) == 0 {
+                Posting::new(doc_id, 1 + next(20), Vec::new())
+            } else {
+                let mut position = 0;
+                let positions: Vec<u32> = (0..1 + next(8))
+                    .map(|_| {
+                        position += next(1000);
+                        position
+                    })
+                    .collect();
+                Posting::new(doc_id, positions.len() as u32, positions)
+            };
+            if next(3) == 0 {
+                posting.fields = [next(3), next(2), 0, next(5)];
+            }
+            postings.push(posting);
+        }
+        postings
+    }
+
+    fn lengths_for(postings: &Postings) -> DocumentLengths {
+        let last = postings.postings.last().map_or(0, |posting| posting.doc_id);
+        DocumentLengths::new((1..=last).map(|doc_id| 50 + doc_id % 200).collect())
+    }
+
+    fn assert_round_trip(postings: &Postings) {
+        let lengths = lengths_for(postings);
+        for codec in BINARY_CODECS {
+            let payload = postings.encode(codec, &lengths);
+            let decoded = Postings::decode("term".to_string(), &payload, codec).unwrap();
+            assert_eq!(
+                contents(&decoded.postings),
+                contents(&postings.postings),
+                "{:?} with {} postings",
+                codec,
+                postings.postings.len()
+            );
+        }
+        let text = Postings::load_postings(&postings.save_postings()).unwrap();
+        assert_eq!(contents(&text.postings), contents(&postings.postings));
+    }
+
+    #[test]
+    fn every_codec_round_trips() {
+        for count in [1, 2, 100, 1000] {
+            assert_round_trip(&sample(count, 50, count as u64));
+        }
+    }
+
+    #[test]
+    fn lists_on_block_and_skip_boundaries_round_trip() {
+        assert_eq!(BLOCK_SIZE, SKIP_INTERVAL);
+        for count in [
+            BLOCK_SIZE - 1,
+            BLOCK_SIZE,
+            BLOCK_SIZE + 1,
+            2 * BLOCK_SIZE,
+            3 * BLOCK_SIZE,
+        ] {
+            assert_round_trip(&sample(count, 10, count as u64));
+        }
+    }
+
+    #[test]
+    fn large_gaps_round_trip() {
+        let mut postings = Postings::new("term".to_string());
+        for (doc_id, position) in [(1, 0), (2, u32::MAX), (1 << 31, 7), (u32::MAX - 1, 1 << 30)] {
+            postings.push(Posting::new(doc_id, 1, vec![position]));
+        }
+        let lengths = DocumentLengths::new(Vec::new());
+        for codec in BINARY_CODECS {
+            let payload = postings.encode(codec, &lengths);
+            let decoded = Postings::decode("term".to_string(), &payload, codec).unwrap();
+            assert_eq!(
+                contents(&decoded.postings),
+                contents(&postings.postings),
+                "{:?}",
+                codec
+            );
+        }
+    }
+
+    #[test]
+    fn packed_values_wider_than_the_width_are_patched() {
+        let mut values: Vec<u32> = (0..BLOCK_SIZE as u32).map(|value| value % 5).collect();
+        values[3] = u32::MAX;
+        values[64] = 1 << 20;
+        values[BLOCK_SIZE - 1] = 1000;
+        let mut bytes = Vec::new();
+        put_packed(&mut bytes, &values);
+        // 3 bits fit the small values, the other three are exceptions
+        assert_eq!(bytes[0], 3);
+        assert_eq!(bytes[1], 3);
+        let mut reader = bytes.as_slice();
+        assert_eq!(get_packed(&mut reader, values.len()).unwrap(), values);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn packed_zeros_and_odd_counts_round_trip() {
+        for values in [vec![0; 7], vec![1], vec![u32::MAX; 3], (0..33).collect()] {
+            let mut bytes = Vec::new();
+            put_packed(&mut bytes, &values);
+            let mut reader = bytes.as_slice();
+            assert_eq!(get_packed(&mut reader, values.len()).unwrap(), values);
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn advance_matches_a_linear_scan() {
+        let postings = sample(5 * BLOCK_SIZE + 17, 40, 7);
+        let lengths = lengths_for(&postings);
+        let last = postings.postings.last().unwrap().doc_id;
+        let targets: Vec<DocId> = (0..last + 50).step_by(37).collect();
+        for codec in BINARY_CODECS {
+            let mut cursor = PostingCursor::new(postings.encode(codec, &lengths), codec).unwrap();
+            for &target in &targets {
+                cursor.advance(target).unwrap();
+                let expected = postings
+                    .postings
+                    .iter()
+                    .find(|posting| posting.doc_id >= target);
+                assert_eq!(
+                    cursor
+                        .posting()
+                        .unwrap()
+                        .map(|posting| contents(&[posting])),
+                    expected.map(|posting| contents(std::slice::from_ref(posting))),
+                    "{:?} advancing to {}",
+                    codec,
+                    target
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn advance_never_moves_backwards() {
+        let postings = sample(3 * BLOCK_SIZE, 5, 11);
+        let lengths = lengths_for(&postings);
+        for codec in BINARY_CODECS {
+            let mut cursor = PostingCursor::new(postings.encode(codec, &lengths), codec).unwrap();
+            let middle = postings.postings[2 * BLOCK_SIZE].doc_id;
+            cursor.advance(middle).unwrap();
+            cursor.advance(1).unwrap();
+            assert_eq!(cursor.doc_id(), Some(middle), "{:?}", codec);
+            // and moves on one posting at a time from there
+            cursor.move_next().unwrap();
+            assert_eq!(
+                cursor.doc_id(),
+                Some(postings.postings[2 * BLOCK_SIZE + 1].doc_id),
+                "{:?}",
+                codec
+            );
+        }
+    }
+
+    #[test]
+    fn score_bounds_cover_every_posting() {
+        let postings = sample(2 * BLOCK_SIZE + 5, 30, 3);
+        let lengths = lengths_for(&postings);
+        let bound = ScoreBound::of(&postings.postings, &lengths);
+        for posting in &postings.postings {
+            assert!(posting.term_freq <= bound.term_freq);
+            assert!(lengths.get(posting.doc_id) >= bound.min_document_length);
+        }
+        for codec in BINARY_CODECS {
+            let cursor = PostingCursor::new(postings.encode(codec, &lengths), codec).unwrap();
+            assert_eq!(cursor.score_bound(), bound, "{:?}", codec);
+        }
+        let payload = postings.encode(PostingsCodec::Block, &lengths);
+        let blocks = PostingBlocks::parse(&payload).unwrap();
+        for (block, chunk) in postings.postings.chunks(BLOCK_SIZE).enumerate() {
+            assert_eq!(blocks.block_bound(block), ScoreBound::of(chunk, &lengths));
+        }
     }
 }
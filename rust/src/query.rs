@@ -1,99 +1,298 @@
+use std::cmp::{Ordering, Reverse};
+use std::time;
 use std::{
-    collections::HashMap,
+    collections::{BinaryHeap, HashMap, HashSet},
     io::{self, Write},
 };
-use std::{fs, time};
 
-use crate::id_book::IDBookElement;
-use crate::index_builder::Document;
+use crate::boolean_query::{minimal_window, QueryNode};
+use crate::config::IndexConfig;
+use crate::document_store::DocumentStore;
+use crate::id_book::{DocumentLengths, DocumentWeights, IDBookElement};
+use crate::index_metadata::IndexMetadata;
+use crate::link_graph::{hits_authorities, LinkGraph};
+use crate::postings::{PostingCursor, ScoreBound};
+use crate::scorer::{build_scorer, Ranking, Scorer, ScoringContext, TermMatch, WeightedRanking};
+use crate::similarity::DuplicateClusters;
+use crate::single_posting::{DocId, Posting};
+use crate::snippet::make_snippets;
 use crate::{file_skip_list, tokenizer::Tokenizer};
-use std::fs::File;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-pub const TOTAL_DOCUMENT_COUNT: u16 = 46843;
+// how many of the best ranked documents get their score adjusted by term proximity and phrases
+const RERANK_DEPTH: usize = 100;
+// in-links added to the HITS base set per root document, popular pages have far too many
+const HITS_MAX_INCOMING: usize = 50;
+const HITS_ITERATIONS: usize = 20;
+// results returned per search
+const RESULT_COUNT: usize = 10;
+
+// (doc id, coverage, score) of a matching document, ranked by coverage and then score
+type RankedDocument = (DocId, f64, f64);
 
 pub struct SearchEngine {
+    config: IndexConfig,
+    metadata: IndexMetadata,
+    context: ScoringContext,
+    scorer: Box<dyn Scorer>,
     query: String,
     tokens: Vec<String>,
-    skiplists: Arc<Vec<Vec<file_skip_list::FileSkip>>>,
+    query_tree: Option<QueryNode>,
+    // None until the page_rank binary has been run on the index
+    page_rank: Option<DocumentWeights>,
+    // largest page_rank_boost of any document, part of every score bound
+    max_page_rank_boost: f64,
+    clusters: DuplicateClusters,
+    documents: DocumentStore,
+    // also written by the page_rank binary, needed for HITS
+    link_graph: Option<LinkGraph>,
+    hits: bool,
+    skiplists: Arc<Vec<file_skip_list::FileSkipList>>,
+    ngram_skiplists: Vec<file_skip_list::FileSkipList>,
 }
 
 impl SearchEngine {
-    pub fn new() -> Self {
-        let mut skiplists = Vec::new();
-        for i in 0..=9 {
-            let skiplist = file_skip_list::FileSkip::read_skip_list((b'0' + i) as char);
-            skiplists.push(skiplist);
+    pub fn new(config: IndexConfig) -> io::Result<Self> {
+        config.check_format_version()?;
+        let metadata = IndexMetadata::load(&config)?;
+        let document_lengths = DocumentLengths::load(&config)?;
+        let document_norms = DocumentWeights::load(&config.document_norms_path())?;
+        if metadata.tokenizer != Tokenizer::new().settings() {
+            println!("Warning: index was built with different tokenizer settings");
         }
-        for i in 0..26 {
-            let skiplist = file_skip_list::FileSkip::read_skip_list((b'a' + i) as char);
-            skiplists.push(skiplist);
+        // the other scorers weigh the fields at query time, the cosine norms were weighed by merge
+        if metadata
+            .norm_field_weights
+            .is_some_and(|weights| weights != config.field_weights)
+        {
+            println!(
+                "Warning: the document norms were merged with other field weights, re-run merge \
+                 for cosine scores to match [field_weights]"
+            );
         }
-        Self {
+        let clusters = DuplicateClusters::load(&config)?;
+        let documents = DocumentStore::load(&config)?;
+        let page_rank =
+            match DocumentWeights::load_stamped(&config.page_rank_path(), metadata.stamp()) {
+                Ok(page_rank) => Some(page_rank),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                // keyed by the doc ids of an earlier build, boosting with it would lift other pages
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    println!("Warning: {}, searching without PageRank", e);
+                    None
+                }
+                Err(e) => return Err(e),
+            };
+        let link_graph = match LinkGraph::load(&config.link_graph_path(), metadata.stamp()) {
+            Ok(link_graph) => Some(link_graph),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            // the same goes for HITS, its edges would join unrelated pages
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                println!("Warning: {}, searching without HITS", e);
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        let skiplists = file_skip_list::read_skip_lists(&config.merged_dir());
+        // without an n-gram index phrases are only boosted through proximity
+        let ngram_skiplists = if metadata.max_ngram >= 2 {
+            file_skip_list::read_skip_lists(&config.merged_ngram_dir())
+        } else {
+            Vec::new()
+        };
+        let mut engine = Self {
+            context: ScoringContext {
+                document_count: metadata.document_count,
+                average_document_length: metadata.average_document_length,
+                document_lengths: Arc::new(document_lengths),
+                document_norms: Arc::new(document_norms),
+                field_weights: config.field_weights,
+            },
+            scorer: build_scorer(config.ranking, &config.linear, &config),
             query: String::new(),
             tokens: Vec::new(),
+            query_tree: None,
+            page_rank,
+            max_page_rank_boost: 1.0,
+            clusters,
+            documents,
+            link_graph,
+            hits: config.hits,
             skiplists: Arc::new(skiplists),
-        }
+            ngram_skiplists,
+            config,
+            metadata,
+        };
+        engine.max_page_rank_boost = (1..=engine.metadata.document_count)
+            .map(|doc_id| engine.page_rank_boost(doc_id))
+            .fold(1.0, f64::max);
+        Ok(engine)
+    }
+
+    pub fn config(&self) -> &IndexConfig {
+        &self.config
+    }
+
+    pub fn metadata(&self) -> &IndexMetadata {
+        &self.metadata
     }
 
-    pub fn get_query(&mut self) {
+    // reads the next query from stdin, false once stdin is closed
+    pub fn get_query(&mut self) -> io::Result<bool> {
         self.query.clear();
         print!("Enter your search query: ");
-        io::stdout().flush().unwrap();
-        io::stdin()
-            .read_line(&mut self.query)
-            .expect("Failed to read query");
+        io::stdout().flush()?;
+        if io::stdin().read_line(&mut self.query)? == 0 {
+            println!();
+            return Ok(false);
+        }
         self.query = self.query.trim().to_string();
-        self.tokens = Tokenizer::new().tokenize(&self.query);
+        self.parse_query();
+        Ok(true)
+    }
+
+    // falls back to the linear weights from the config when none are given
+    pub fn set_ranking(&mut self, ranking: Ranking, linear: Option<&[WeightedRanking]>) {
+        let linear = linear.unwrap_or(&self.config.linear);
+        self.scorer = build_scorer(ranking, linear, &self.config);
+    }
+
+    pub fn set_hits(&mut self, hits: bool) {
+        self.hits = hits;
+    }
+
+    pub fn set_scorer(&mut self, scorer: Box<dyn Scorer>) {
+        self.scorer = scorer;
     }
 
     pub fn set_query(&mut self, query: String) {
         self.query = query;
-        self.tokens = Tokenizer::new().tokenize(&self.query);
+        self.parse_query();
     }
 
-    pub fn search(&self) -> (Vec<(String, String)>, u128) {
+    fn parse_query(&mut self) {
+        let tokenizer = Tokenizer::new();
+        self.query_tree = QueryNode::parse(&self.query, &tokenizer);
+        self.tokens = tokenizer.tokenize(&self.query);
+    }
+
+    pub fn search(&self) -> (Vec<SearchHit>, u128) {
         let time = time::Instant::now();
         println!("Searching for: \"{}\"", self.query);
         println!("Tokens: {:?}", self.tokens);
 
-        // This will be shared across threads for adding candidates
-        let candidates = Arc::new(Mutex::new(Vec::with_capacity(self.tokens.len())));
-        let mut handles = vec![];
+        let query_tree = match &self.query_tree {
+            Some(query_tree) => query_tree,
+            None => return (Vec::new(), 0),
+        };
+        println!("Query: {:?}", query_tree);
 
-        for token in self.tokens.iter() {
-            let candidates = Arc::clone(&candidates);
-            let skiplists = Arc::clone(&self.skiplists);
-            let token = token.clone();
+        // every term in the query is looked up once and weighted by how often it was repeated
+        let query_terms = QueryNode::term_frequencies(&self.query, &Tokenizer::new());
+        // terms that only ever appear negated don't count towards what a result is missing
+        let positive_terms: Vec<String> = query_terms
+            .iter()
+            .filter(|(_, query_term_freq)| *query_term_freq > 0)
+            .map(|(term, _)| term.clone())
+            .collect();
 
-            let handle = thread::spawn(move || {
-                let first_char = token.chars().next().unwrap();
-                let first_char_index = if first_char.is_ascii_digit() {
-                    (first_char as u8 - b'0') as usize
-                } else {
-                    (first_char as u8 - b'a') as usize + 10
-                };
+        let (mut sorted_candidates, mut candidates, pruned) = self.rank(
+            query_tree,
+            &query_terms,
+            &positive_terms,
+            self.config.dynamic_pruning,
+        );
+        let mut shown = self.collapse(&sorted_candidates);
+        // pruning keeps the RERANK_DEPTH best documents, which only runs short when nearly all of
+        // them fold into others
+        if pruned && shown.len() < RESULT_COUNT {
+            println!("Too many of the best results were folded, ranking every match");
+            (sorted_candidates, candidates, _) =
+                self.rank(query_tree, &query_terms, &positive_terms, false);
+            shown = self.collapse(&sorted_candidates);
+        }
+
+        let mut results = Vec::new();
+        let tokenizer = Tokenizer::new();
+        let final_time = time.elapsed().as_millis();
+        println!("Search took: {}ms", final_time);
+        for (doc, score, folded) in shown {
+            let doc_id = doc.id;
+            let missing_terms: Vec<String> = positive_terms
+                .iter()
+                .filter(|term| {
+                    candidates
+                        .get(*term)
+                        .is_none_or(|candidate| !candidate.doc_ids.contains_key(&doc_id))
+                })
+                .cloned()
+                .collect();
+            println!(
+                "{}|> {}: {} (Score: {}, Missing: {:?}, Folded: {})",
+                doc_id,
+                doc.url,
+                doc.path.display(),
+                score,
+                missing_terms,
+                folded
+            );
+            let stored = match self.documents.get(doc_id) {
+                Ok(stored) => stored,
+                Err(e) => {
+                    println!(
+                        "Error reading doc_id {} from the document store: {}",
+                        doc_id, e
+                    );
+                    continue;
+                }
+            };
+            let title = stored.title.unwrap_or_else(|| stored.url.clone());
+            let snippets = make_snippets(&stored.text, &positive_terms, &tokenizer);
+            println!("    {}", title);
+            for snippet in &snippets {
+                println!("    {}", snippet);
+            }
+            results.push(SearchHit {
+                doc_id,
+                url: stored.url,
+                title,
+                snippets,
+                score,
+                missing_terms,
+                folded,
+            });
+        }
+        (results, final_time)
+    }
 
-                let offset_range =
-                    file_skip_list::FileSkip::find_skip_entry(&skiplists[first_char_index], &token);
+    // The matches of the query ranked best first, with the candidates holding the postings they
+    // matched. With dynamic pruning a disjunction only keeps its
+    // RERANK_DEPTH best documents, the flag tells whether any were left out
+    fn rank(
+        &self,
+        query_tree: &QueryNode,
+        query_terms: &[(String, u32)],
+        positive_terms: &[String],
+        dynamic_pruning: bool,
+    ) -> (Vec<RankedDocument>, HashMap<String, Candidate>, bool) {
+        // every term's postings are opened as a cursor, looked up in parallel
+        let cursors = Arc::new(Mutex::new(HashMap::with_capacity(query_terms.len())));
+        let mut handles = vec![];
 
-                let file_path = format!("inverted_index/merged/{}.txt", first_char);
+        for (token, _) in query_terms {
+            let token = token.clone();
+            let cursors = Arc::clone(&cursors);
+            let skiplists = Arc::clone(&self.skiplists);
+            let merged_dir = self.config.merged_dir();
+            let codec = self.metadata.postings_codec;
+            let lengths = Arc::clone(&self.context.document_lengths);
 
-                let mut candidate = Candidate::new(token.to_string());
-                if let Ok(file) = File::open(&file_path) {
-                    let postings =
-                        file_skip_list::get_postings_from_offset_range(&file, offset_range, &token);
-                    let posting_length = postings.postings.len() as u16;
-                    for single_posting in postings.postings {
-                        let score = scoring_tf_idf(single_posting.term_freq, posting_length);
-                        candidate.update_score(single_posting.doc_id, score);
-                    }
-                } else {
-                    println!("Warning: Could not open index file for '{}'", first_char);
-                }
-                let mut candidates = candidates.lock().unwrap();
-                candidates.push(candidate);
+            let handle = thread::spawn(move || {
+                let cursor =
+                    file_skip_list::lookup_cursor(&skiplists, &merged_dir, &token, codec, &lengths);
+                let mut cursors = cursors.lock().unwrap();
+                cursors.insert(token, cursor);
             });
             handles.push(handle);
         }
@@ -102,77 +301,747 @@ impl SearchEngine {
             handle.join().unwrap();
         }
 
-        if self.tokens.len() == 0 {
-            return (Vec::new(), 0);
-        }
+        let mut cursors: HashMap<String, PostingCursor> =
+            Arc::try_unwrap(cursors).unwrap().into_inner().unwrap();
+        let mut candidates: HashMap<String, Candidate> = query_terms
+            .iter()
+            .map(|(token, query_term_freq)| {
+                let document_frequency = cursors[token].document_frequency() as u32;
+                let candidate = Candidate::new(token.clone(), *query_term_freq, document_frequency);
+                (token.clone(), candidate)
+            })
+            .collect();
+        // a disjunction only needs the postings of its best documents when the scorer bounds
+        // every term, a conjunction only those of the documents holding every term, anything else
+        // is evaluated over the whole lists
+        let disjunction = query_tree
+            .disjunction_terms()
+            .filter(|_| dynamic_pruning)
+            .and_then(|terms| {
+                let bounds = terms
+                    .iter()
+                    .map(|term| self.score_bound(&candidates[*term], &cursors[*term].score_bound()))
+                    .collect::<Option<Vec<f64>>>()?;
+                Some((terms, bounds))
+            });
+        let walked = match (disjunction, query_tree.conjunction_terms()) {
+            (Some((terms, bounds)), _) => top_k(
+                &mut cursors,
+                &terms,
+                &bounds,
+                &mut candidates,
+                RERANK_DEPTH,
+                |doc_id, matches| {
+                    self.scorer.score_document(&self.context, doc_id, matches)
+                        * self.page_rank_boost(doc_id)
+                },
+                |candidate, bound| self.score_bound(candidate, bound),
+            ),
+            (None, Some((terms, negated))) => {
+                intersect(&mut cursors, &terms, &negated, &mut candidates).map(|_| false)
+            }
+            (None, None) => {
+                read_all_postings(&mut cursors, &mut candidates);
+                Ok(false)
+            }
+        };
+        let pruned = walked.unwrap_or_else(|e| {
+            println!("Error reading postings: {}", e);
+            false
+        });
+        let mut all_candidates = query_tree.evaluate(&candidates, self.metadata.document_count);
 
-        let mut candidates = Arc::try_unwrap(candidates).unwrap().into_inner().unwrap();
-        if candidates.len() == 0 {
-            return (Vec::new(), 0);
+        // soft AND: too few documents match every term of a plain query, so documents matching
+        // only some of them are ranked after the full matches, by the idf of the terms they contain
+        let soft_and = all_candidates.len() < self.config.soft_and_min_results
+            && positive_terms.len() > 1
+            && query_tree.is_bare_conjunction();
+        let mut full_matches = HashSet::new();
+        if soft_and {
+            // the intersection skipped the documents missing a term, which are needed now
+            read_all_postings(&mut cursors, &mut candidates);
+            full_matches.extend(all_candidates.keys().copied());
+            let negated_terms: Vec<&Candidate> = candidates
+                .values()
+                .filter(|candidate| candidate.query_term_freq == 0)
+                .collect();
+            for term in positive_terms {
+                let partial_matches = QueryNode::Term(term.clone())
+                    .evaluate(&candidates, self.metadata.document_count);
+                for (doc_id, matches) in partial_matches {
+                    if full_matches.contains(&doc_id)
+                        || negated_terms
+                            .iter()
+                            .any(|negated| negated.doc_ids.contains_key(&doc_id))
+                    {
+                        continue;
+                    }
+                    all_candidates.entry(doc_id).or_default().extend(matches);
+                }
+            }
+            println!(
+                "Only {} full matches, falling back to {} partial matches",
+                full_matches.len(),
+                all_candidates.len() - full_matches.len()
+            );
         }
-        candidates.sort_by(|a: &Candidate, b: &Candidate| a.doc_ids.len().cmp(&b.doc_ids.len()));
 
-        let mut all_candidates: HashMap<u16, f64> = if !candidates.is_empty() {
-            candidates[0].doc_ids.clone()
-        } else {
-            HashMap::new()
+        // only the documents that matched the query get scored
+        let mut sorted_candidates: Vec<RankedDocument> = all_candidates
+            .iter()
+            .map(|(&doc_id, matches)| {
+                let score = self.scorer.score_document(&self.context, doc_id, matches)
+                    * self.page_rank_boost(doc_id);
+                let coverage = if !soft_and || full_matches.contains(&doc_id) {
+                    f64::INFINITY
+                } else {
+                    positive_terms
+                        .iter()
+                        .filter_map(|term| candidates.get(term))
+                        .filter(|candidate| candidate.doc_ids.contains_key(&doc_id))
+                        .map(|candidate| candidate.idf(self.metadata.document_count))
+                        .sum()
+                };
+                (doc_id, coverage, score)
+            })
+            .collect();
+        let by_coverage_then_score = |a: &RankedDocument, b: &RankedDocument| {
+            b.1.partial_cmp(&a.1)
+                .unwrap()
+                .then(b.2.partial_cmp(&a.2).unwrap())
         };
+        sorted_candidates.sort_by(by_coverage_then_score);
 
-        // boolean AND
-        for candidate in candidates.iter().skip(1) {
-            all_candidates.retain(|doc_id, score| {
-                if let Some(candidate_score) = candidate.doc_ids.get(doc_id) {
-                    *score += candidate_score;
-                    true
-                } else {
-                    false
+        // proximity: the best documents get boosted when their query terms sit close together
+        let depth = sorted_candidates.len().min(RERANK_DEPTH);
+        if self.config.proximity_weight > 0.0 && positive_terms.len() > 1 {
+            for (doc_id, _, score) in sorted_candidates[..depth].iter_mut() {
+                *score *= 1.0
+                    + self.config.proximity_weight
+                        * self.proximity(*doc_id, positive_terms, &candidates);
+            }
+        }
+        // phrases: and again by the share of the query's n-grams they contain
+        let reranked: Vec<DocId> = sorted_candidates[..depth]
+            .iter()
+            .map(|&(doc_id, _, _)| doc_id)
+            .collect();
+        let ngrams = self.query_ngrams(query_tree, &reranked);
+        if self.config.phrase_weight > 0.0 && !ngrams.is_empty() {
+            for (doc_id, _, score) in sorted_candidates[..depth].iter_mut() {
+                let contained = ngrams
+                    .iter()
+                    .filter(|documents| documents.contains(doc_id))
+                    .count();
+                *score *= 1.0 + self.config.phrase_weight * contained as f64 / ngrams.len() as f64;
+            }
+        }
+        sorted_candidates[..depth].sort_by(by_coverage_then_score);
+
+        // HITS: the best results are lifted by how much the pages around them point at them
+        if self.hits {
+            match &self.link_graph {
+                Some(link_graph) => {
+                    let root_size = sorted_candidates.len().min(self.config.hits_root_size);
+                    let root: Vec<DocId> = sorted_candidates[..root_size]
+                        .iter()
+                        .map(|&(doc_id, _, _)| doc_id)
+                        .collect();
+                    let authorities =
+                        hits_authorities(link_graph, &root, HITS_MAX_INCOMING, HITS_ITERATIONS);
+                    for (doc_id, _, score) in sorted_candidates[..root_size].iter_mut() {
+                        *score *= 1.0 + self.config.hits_weight * authorities[doc_id];
+                    }
+                    sorted_candidates[..root_size].sort_by(by_coverage_then_score);
                 }
-            });
+                None => println!("Warning: no link graph, run the page_rank binary for HITS"),
+            }
         }
+        (sorted_candidates, candidates, pruned)
+    }
 
-        let mut sorted_candidates: Vec<(&u16, &f64)> = all_candidates.iter().collect();
-        sorted_candidates.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
-        let mut results = Vec::new();
-        let final_time = time.elapsed().as_millis();
-        println!("Search took: {}ms", final_time);
-        for (doc_id, score) in sorted_candidates.iter().take(10) {
-            let doc = IDBookElement::get_doc_from_id(**doc_id);
-            println!(
-                "{}|> {}: {} (Score: {})",
-                doc_id,
-                doc.url,
-                doc.path.display(),
-                score
-            );
-            let content: String = fs::read_to_string(&doc.path).unwrap();
+    // Picks the RESULT_COUNT results to show from the ranked candidates. A result in the same
+    // duplicate cluster as a better one, or past max_results_per_host for its host, is folded
+    // into the best shown result of that cluster or host instead. Folds are counted up to
+    // RERANK_DEPTH candidates deep
+    fn collapse(&self, sorted_candidates: &[RankedDocument]) -> Vec<(IDBookElement, f64, usize)> {
+        let mut shown: Vec<(IDBookElement, f64, usize)> = Vec::new();
+        // cluster -> index in shown, host -> indexes in shown
+        let mut shown_clusters: HashMap<DocId, usize> = HashMap::new();
+        let mut shown_hosts: HashMap<String, Vec<usize>> = HashMap::new();
+        for (rank, &(doc_id, _, score)) in sorted_candidates.iter().enumerate() {
+            if shown.len() == RESULT_COUNT && rank >= RERANK_DEPTH {
+                break;
+            }
+            if self.config.collapse_duplicates {
+                if let Some(&index) = shown_clusters.get(&self.clusters.cluster(doc_id)) {
+                    shown[index].2 += 1;
+                    continue;
+                }
+            }
+            let doc = IDBookElement::get_doc_from_id(&self.config, doc_id);
+            let host = doc.get_domain();
+            let max_per_host = self.config.max_results_per_host;
+            match shown_hosts.get(&host) {
+                Some(indexes) if max_per_host > 0 && indexes.len() >= max_per_host => {
+                    shown[indexes[0]].2 += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            if shown.len() == RESULT_COUNT {
+                continue;
+            }
+            shown_clusters.insert(self.clusters.cluster(doc_id), shown.len());
+            shown_hosts.entry(host).or_default().push(shown.len());
+            shown.push((doc, score, 0));
+        }
+        shown
+    }
 
-            let document: Document = serde_json::from_str(&content).unwrap();
-            results.push((doc.url.clone(), document.content));
+    // PageRank times the document count is 1 for an average page, the log keeps a few heavily
+    // linked pages from drowning out relevance
+    fn page_rank_boost(&self, doc_id: DocId) -> f64 {
+        match &self.page_rank {
+            Some(page_rank) if self.config.page_rank_weight > 0.0 => {
+                let relative = page_rank.get(doc_id) * self.metadata.document_count as f64;
+                1.0 + self.config.page_rank_weight * relative.ln_1p()
+            }
+            _ => 1.0,
         }
-        (results, final_time)
     }
+
+    // Largest score a document can get from a term whose frequencies and length are within
+    // bound, PageRank included. Never negative, so the bound of more terms is never lower
+    fn score_bound(&self, candidate: &Candidate, bound: &ScoreBound) -> Option<f64> {
+        let term = TermMatch {
+            document_frequency: candidate.document_frequency,
+            term_freq: bound.term_freq,
+            fields: bound.fields,
+            query_term_freq: candidate.query_term_freq,
+        };
+        let score = self
+            .scorer
+            .max_term_score(&self.context, &term, bound.min_document_length)?;
+        Some(score * self.max_page_rank_boost)
+    }
+
+    // which of the given documents contain each n-gram of the query, looked up in the n-gram index
+    fn query_ngrams(&self, query_tree: &QueryNode, doc_ids: &[DocId]) -> Vec<HashSet<DocId>> {
+        if self.ngram_skiplists.is_empty() {
+            return Vec::new();
+        }
+        let merged_dir = self.config.merged_ngram_dir();
+        let mut doc_ids = doc_ids.to_vec();
+        doc_ids.sort_unstable();
+        query_tree
+            .ngrams(self.metadata.max_ngram)
+            .iter()
+            .map(|ngram| {
+                file_skip_list::lookup_postings_of(
+                    &self.ngram_skiplists,
+                    &merged_dir,
+                    ngram,
+                    self.metadata.postings_codec,
+                    &doc_ids,
+                )
+                .iter()
+                .map(|posting| posting.doc_id)
+                .collect()
+            })
+            .collect()
+    }
+
+    // (matched terms - 1) / span of the smallest window holding every matched term, so 1.0 for
+    // adjacent terms and 0.0 when fewer than two terms appear in the document
+    fn proximity(
+        &self,
+        doc_id: DocId,
+        terms: &[String],
+        candidates: &HashMap<String, Candidate>,
+    ) -> f64 {
+        let positions: Vec<&[u32]> = terms
+            .iter()
+            .filter_map(|term| candidates.get(term)?.doc_ids.get(&doc_id))
+            .map(|posting| posting.positions.as_slice())
+            // terms matched only through a field have no body positions to measure
+            .filter(|positions| !positions.is_empty())
+            .collect();
+        if positions.len() < 2 {
+            return 0.0;
+        }
+        match minimal_window(&positions) {
+            Some(span) if span > 0 => (positions.len() - 1) as f64 / span as f64,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub doc_id: DocId,
+    pub url: String,
+    // the page's title, or its url when it has none
+    pub title: String,
+    // passages around the query terms, HTML-escaped with the terms in <mark></mark>
+    pub snippets: Vec<String>,
+    pub score: f64,
+    // query terms the document doesn't contain, only non-empty for OR queries and soft AND matches
+    pub missing_terms: Vec<String>,
+    // results left out because they duplicate this one or share its host
+    pub folded: usize,
+}
+
+// A document kept by top_k with the postings it matched, ordered by score
+struct ScoredDocument {
+    score: f64,
+    doc_id: DocId,
+    postings: Vec<(String, Posting)>,
 }
 
+impl Ord for ScoredDocument {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then(other.doc_id.cmp(&self.doc_id))
+    }
+}
+
+impl PartialOrd for ScoredDocument {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ScoredDocument {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScoredDocument {}
+
 #[derive(Debug)]
 pub struct Candidate {
     pub term: String,
-    pub doc_ids: HashMap<u16, f64>, // for each doc_id, the tfidf score
+    pub doc_ids: HashMap<DocId, Posting>, // for each doc_id, its posting with term frequency and positions
+    pub query_term_freq: u32,
+    // length of the whole postings list, doc_ids may only hold the documents that matched
+    pub document_frequency: u32,
 }
 
 impl Candidate {
-    pub fn new(token: String) -> Self {
+    pub fn new(token: String, query_term_freq: u32, document_frequency: u32) -> Self {
         Self {
             term: token,
             doc_ids: HashMap::new(),
+            query_term_freq,
+            document_frequency,
+        }
+    }
+    pub fn insert(&mut self, posting: Posting) {
+        self.doc_ids.insert(posting.doc_id, posting);
+    }
+
+    pub fn idf(&self, document_count: DocId) -> f64 {
+        if self.document_frequency == 0 {
+            return 0.0;
+        }
+        f64::log10(document_count as f64 / self.document_frequency as f64)
+    }
+
+    pub fn term_match(&self, posting: &Posting) -> TermMatch {
+        TermMatch {
+            document_frequency: self.document_frequency,
+            term_freq: posting.term_freq,
+            fields: posting.fields,
+            query_term_freq: self.query_term_freq,
+        }
+    }
+}
+
+// Fills the candidates with every posting of their terms
+fn read_all_postings(
+    cursors: &mut HashMap<String, PostingCursor>,
+    candidates: &mut HashMap<String, Candidate>,
+) {
+    for (term, cursor) in cursors.iter_mut() {
+        let candidate = candidates.get_mut(term).unwrap();
+        let result = cursor.rewind().and_then(|_| {
+            while let Some(posting) = cursor.posting()? {
+                candidate.insert(posting);
+                cursor.move_next()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            println!("Error reading the postings of '{}': {}", term, e);
+        }
+    }
+}
+
+// Block-Max WAND: the k best documents holding any of the terms, found document at a time
+// while skipping the ones whose score bound can't beat the k-th best so far. With the cursors
+// in doc id order, the pivot is the first one at which the list bounds of it and the cursors
+// before it add up past that threshold, no document before the pivot's can. The block bounds
+// around the pivot's document then rule out whole blocks before they are decoded. Fills the
+// candidates with the postings of the k documents, returns whether any match was left out. The
+// scores and bounds come from the search engine's scorer, PageRank included
+fn top_k(
+    cursors: &mut HashMap<String, PostingCursor>,
+    terms: &[&String],
+    bounds: &[f64],
+    candidates: &mut HashMap<String, Candidate>,
+    k: usize,
+    score_document: impl Fn(DocId, &[TermMatch]) -> f64,
+    score_bound: impl Fn(&Candidate, &ScoreBound) -> Option<f64>,
+) -> io::Result<bool> {
+    // (term, cursor, bound of its whole list)
+    let mut walking: Vec<(&String, &mut PostingCursor, f64)> = Vec::with_capacity(terms.len());
+    for (term, cursor) in cursors.iter_mut() {
+        if let Some(index) = terms.iter().position(|other| *other == term) {
+            walking.push((term, cursor, bounds[index]));
+        }
+    }
+    let mut best: BinaryHeap<Reverse<ScoredDocument>> = BinaryHeap::with_capacity(k + 1);
+    // set whenever a matching document is passed over or pushed out of the k best
+    let mut left_out = false;
+
+    loop {
+        walking.retain(|(_, cursor, _)| cursor.doc_id().is_some());
+        walking.sort_by_key(|(_, cursor, _)| cursor.doc_id());
+        // a document has to beat the k-th best score to get in
+        let threshold = match best.peek() {
+            Some(Reverse(kth)) if best.len() == k => kth.score,
+            _ => f64::NEG_INFINITY,
+        };
+        let mut total = 0.0;
+        let Some(mut pivot) = walking.iter().position(|(_, _, bound)| {
+            total += bound;
+            total > threshold
+        }) else {
+            // the documents the cursors are still on, and any after them, can't make it
+            left_out |= !walking.is_empty();
+            break;
+        };
+        let pivot_doc_id = walking[pivot].1.doc_id().unwrap();
+        while walking
+            .get(pivot + 1)
+            .is_some_and(|(_, cursor, _)| cursor.doc_id() == Some(pivot_doc_id))
+        {
+            pivot += 1;
+        }
+
+        // up to the end of the shortest block holding the pivot's document, documents from it
+        // on can only hold the terms up to the pivot
+        let mut skip_to = walking
+            .get(pivot + 1)
+            .and_then(|(_, cursor, _)| cursor.doc_id())
+            .unwrap_or(DocId::MAX);
+        let mut block_total = 0.0;
+        for (term, cursor, bound) in &walking[..=pivot] {
+            if let Some((block_end, block_bound)) = cursor.block_bound(pivot_doc_id) {
+                block_total += score_bound(&candidates[*term], &block_bound).unwrap_or(*bound);
+                skip_to = skip_to.min(block_end.saturating_add(1));
+            }
         }
+        if block_total <= threshold {
+            // at least the pivot's document
+            left_out = true;
+            for (_, cursor, _) in walking[..=pivot].iter_mut() {
+                cursor.advance(skip_to)?;
+            }
+            continue;
+        }
+
+        if walking[0].1.doc_id() != Some(pivot_doc_id) {
+            left_out = true;
+            for (_, cursor, _) in walking[..pivot].iter_mut() {
+                cursor.advance(pivot_doc_id)?;
+            }
+            continue;
+        }
+        // every cursor up to the pivot is on its document, only now are its postings decoded
+        let postings = walking[..=pivot]
+            .iter()
+            .map(|(term, cursor, _)| Ok((*term, cursor.posting()?.unwrap())))
+            .collect::<io::Result<Vec<(&String, Posting)>>>()?;
+        let matches: Vec<TermMatch> = postings
+            .iter()
+            .map(|(term, posting)| candidates[*term].term_match(posting))
+            .collect();
+        let score = score_document(pivot_doc_id, &matches);
+        if score > threshold {
+            best.push(Reverse(ScoredDocument {
+                score,
+                doc_id: pivot_doc_id,
+                postings: postings
+                    .into_iter()
+                    .map(|(term, posting)| (term.clone(), posting))
+                    .collect(),
+            }));
+            if best.len() > k {
+                best.pop();
+                left_out = true;
+            }
+        } else {
+            left_out = true;
+        }
+        for (_, cursor, _) in walking[..=pivot].iter_mut() {
+            cursor.move_next()?;
+        }
+    }
+
+    for Reverse(document) in best {
+        for (term, posting) in document.postings {
+            candidates.get_mut(&term).unwrap().insert(posting);
+        }
+    }
+    Ok(left_out)
+}
+
+// Finds the documents holding every term and none of the negated ones by walking the rarest
+// term's postings and moving the other cursors forward to each of its documents, so the longer
+// lists are skipped through instead of decoded. Only the postings of the matches go into the
+// candidates
+fn intersect(
+    cursors: &mut HashMap<String, PostingCursor>,
+    terms: &[&String],
+    negated: &[&String],
+    candidates: &mut HashMap<String, Candidate>,
+) -> io::Result<()> {
+    if negated.iter().any(|term| terms.contains(term)) {
+        return Ok(());
     }
-    pub fn update_score(&mut self, doc_id: u16, score: f64) {
-        self.doc_ids.insert(doc_id, score);
+    let mut walking: Vec<(&String, &mut PostingCursor)> = Vec::with_capacity(terms.len());
+    let mut excluded: Vec<&mut PostingCursor> = Vec::with_capacity(negated.len());
+    for (term, cursor) in cursors.iter_mut() {
+        if terms.contains(&term) {
+            walking.push((term, cursor));
+        } else if negated.contains(&term) {
+            excluded.push(cursor);
+        }
     }
+    walking.sort_by_key(|(_, cursor)| cursor.document_frequency());
+    let (driver, others) = walking.split_first_mut().unwrap();
+
+    'documents: while let Some(doc_id) = driver.1.doc_id() {
+        for (_, cursor) in others.iter_mut() {
+            cursor.advance(doc_id)?;
+            match cursor.doc_id() {
+                None => break 'documents,
+                Some(other_doc_id) if other_doc_id > doc_id => {
+                    driver.1.advance(other_doc_id)?;
+                    continue 'documents;
+                }
+                _ => {}
+            }
+        }
+        let mut is_excluded = false;
+        for cursor in excluded.iter_mut() {
+            cursor.advance(doc_id)?;
+            is_excluded |= cursor.doc_id() == Some(doc_id);
+        }
+        if !is_excluded {
+            for (term, cursor) in std::iter::once(&*driver).chain(others.iter()) {
+                let candidate = candidates.get_mut(*term).unwrap();
+                candidate.insert(cursor.posting()?.unwrap());
+            }
+        }
+        driver.1.move_next()?;
+    }
+    Ok(())
 }
 
-pub fn scoring_tf_idf(term_freq: u16, posting_length: u16) -> f64 {
-    let tf: f64 = f64::log10(term_freq as f64) + 1.0;
-    let idf: f64 = f64::log10(TOTAL_DOCUMENT_COUNT as f64 / posting_length as f64);
-    tf * idf
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postings::{Postings, PostingsCodec};
+    use crate::scorer::{Bm25Params, Bm25Scorer, FieldWeights, TfIdfScorer};
+
+    const DOCUMENT_COUNT: DocId = 5000;
+
+    // a term in every `spread`-th document on average, with frequencies and lengths from a few
+    // values so that many documents tie. Only every other run of 500 documents has some that hold
+    // it more often or in the title, so the blocks in between have a lower bound than their list
+    fn sample(term: &str, spread: u32, seed: u64) -> Postings {
+        let mut state = seed;
+        let mut next = |bound: u32| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 33) % bound as u64) as u32
+        };
+        let mut postings = Postings::new(term.to_string());
+        for doc_id in 1..=DOCUMENT_COUNT {
+            if next(spread) == 0 {
+                let mut posting = Posting::new(doc_id, 1 + next(2), Vec::new());
+                if (doc_id / 500) % 2 == 0 && next(20) == 0 {
+                    posting.term_freq = 3 + next(4) * 5;
+                    posting.fields[0] = next(2);
+                }
+                postings.push(posting);
+            }
+        }
+        postings
+    }
+
+    fn context() -> ScoringContext {
+        let lengths: Vec<u32> = (0..DOCUMENT_COUNT)
+            .map(|doc_id| 100 * (1 + doc_id % 3))
+            .collect();
+        ScoringContext {
+            document_count: DOCUMENT_COUNT,
+            average_document_length: 200.0,
+            document_lengths: Arc::new(DocumentLengths::new(lengths)),
+            document_norms: Arc::new(DocumentWeights::new(Vec::new())),
+            field_weights: FieldWeights::default(),
+        }
+    }
+
+    // (doc id, score) of every document, best first with ties in doc id order
+    fn ranked(
+        matches: HashMap<DocId, Vec<TermMatch>>,
+        score_document: impl Fn(DocId, &[TermMatch]) -> f64,
+    ) -> Vec<(DocId, f64)> {
+        let mut ranked: Vec<(DocId, f64)> = matches
+            .iter()
+            .map(|(&doc_id, matches)| (doc_id, score_document(doc_id, matches)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked
+    }
+
+    // the k best documents and whether any match was left out, scoring every match
+    fn full_scan(
+        lists: &[Postings],
+        k: usize,
+        score_document: impl Fn(DocId, &[TermMatch]) -> f64,
+    ) -> (Vec<(DocId, f64)>, bool) {
+        let mut matches: HashMap<DocId, Vec<TermMatch>> = HashMap::new();
+        for list in lists {
+            let candidate = Candidate::new(list.word.clone(), 1, list.postings.len() as u32);
+            for posting in &list.postings {
+                matches
+                    .entry(posting.doc_id)
+                    .or_default()
+                    .push(candidate.term_match(posting));
+            }
+        }
+        let mut best = ranked(matches, score_document);
+        let left_out = best.len() > k;
+        best.truncate(k);
+        (best, left_out)
+    }
+
+    // the same with top_k walking the lists encoded in the codec
+    fn pruned(
+        lists: &[Postings],
+        codec: PostingsCodec,
+        k: usize,
+        lengths: &DocumentLengths,
+        score_document: impl Fn(DocId, &[TermMatch]) -> f64 + Copy,
+        score_bound: impl Fn(&Candidate, &ScoreBound) -> Option<f64> + Copy,
+    ) -> (Vec<(DocId, f64)>, bool) {
+        let mut cursors: HashMap<String, PostingCursor> = lists
+            .iter()
+            .map(|list| {
+                let cursor = PostingCursor::new(list.encode(codec, lengths), codec).unwrap();
+                (list.word.clone(), cursor)
+            })
+            .collect();
+        let mut candidates: HashMap<String, Candidate> = lists
+            .iter()
+            .map(|list| {
+                let document_frequency = cursors[&list.word].document_frequency() as u32;
+                let candidate = Candidate::new(list.word.clone(), 1, document_frequency);
+                (list.word.clone(), candidate)
+            })
+            .collect();
+        let terms: Vec<&String> = lists.iter().map(|list| &list.word).collect();
+        let bounds: Vec<f64> = terms
+            .iter()
+            .map(|term| score_bound(&candidates[*term], &cursors[*term].score_bound()).unwrap())
+            .collect();
+        let left_out = top_k(
+            &mut cursors,
+            &terms,
+            &bounds,
+            &mut candidates,
+            k,
+            score_document,
+            score_bound,
+        )
+        .unwrap();
+
+        let mut matches: HashMap<DocId, Vec<TermMatch>> = HashMap::new();
+        for candidate in candidates.values() {
+            for posting in candidate.doc_ids.values() {
+                matches
+                    .entry(posting.doc_id)
+                    .or_default()
+                    .push(candidate.term_match(posting));
+            }
+        }
+        (ranked(matches, score_document), left_out)
+    }
+
+    #[test]
+    fn top_k_finds_the_best_documents_of_a_full_scan() {
+        let context = context();
+        let scorers: [(&str, Box<dyn Scorer>); 2] = [
+            ("tf_idf", Box::new(TfIdfScorer)),
+            (
+                "bm25",
+                Box::new(Bm25Scorer {
+                    params: Bm25Params::default(),
+                }),
+            ),
+        ];
+        for (name, scorer) in &scorers {
+            // the terms are summed in one order so equal documents tie exactly, whichever order
+            // top_k found them in
+            let score_document = |doc_id: DocId, matches: &[TermMatch]| {
+                let mut matches = matches.to_vec();
+                matches.sort_by_key(|term| term.document_frequency);
+                scorer.score_document(&context, doc_id, &matches)
+            };
+            let score_bound = |candidate: &Candidate, bound: &ScoreBound| {
+                let term = TermMatch {
+                    document_frequency: candidate.document_frequency,
+                    term_freq: bound.term_freq,
+                    fields: bound.fields,
+                    query_term_freq: candidate.query_term_freq,
+                };
+                scorer.max_term_score(&context, &term, bound.min_document_length)
+            };
+            for seed in 0..6 {
+                let lists = [
+                    sample("common", 2, 3 * seed + 1),
+                    sample("middle", 10, 3 * seed + 2),
+                    sample("rare", 60, 3 * seed + 3),
+                ];
+                for codec in [PostingsCodec::Block, PostingsCodec::Varbyte] {
+                    for k in [1, 10, 100, 3000, DOCUMENT_COUNT as usize] {
+                        assert_eq!(
+                            pruned(
+                                &lists,
+                                codec,
+                                k,
+                                &context.document_lengths,
+                                score_document,
+                                score_bound
+                            ),
+                            full_scan(&lists, k, score_document),
+                            "{} with {:?}, seed {}, k = {}",
+                            name,
+                            codec,
+                            seed,
+                            k
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
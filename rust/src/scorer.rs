@@ -0,0 +1,345 @@
+use crate::config::IndexConfig;
+use crate::id_book::{DocumentLengths, DocumentWeights};
+use crate::single_posting::{DocId, FieldFreqs, TermFreq};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Which scorer ranks the results, chosen in the config or per request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Ranking {
+    #[default]
+    TfIdf,
+    Cosine,
+    Bm25,
+    // weighted sum of the other scorers, see IndexConfig::linear
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bm25Params {
+    // term frequency saturation
+    pub k1: f64,
+    // how strongly document length is normalized, 0 disables it
+    pub b: f64,
+}
+
+impl Default for Bm25Params {
+    fn default() -> Self {
+        Self { k1: 1.2, b: 0.75 }
+    }
+}
+
+// How much one occurrence in each field counts compared to one in the body text.
+// Headings and emphasis are also part of the body, so their weight is added on top of it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FieldWeights {
+    pub title: f64,
+    pub heading: f64,
+    pub emphasis: f64,
+    pub anchor: f64,
+}
+
+impl Default for FieldWeights {
+    fn default() -> Self {
+        Self {
+            title: 5.0,
+            heading: 2.0,
+            emphasis: 0.5,
+            anchor: 3.0,
+        }
+    }
+}
+
+impl FieldWeights {
+    // field-weighted pseudo term frequency
+    pub fn weighted_freq(&self, term_freq: TermFreq, fields: &FieldFreqs) -> f64 {
+        let [title, heading, emphasis, anchor] = fields.map(|freq| freq as f64);
+        term_freq as f64
+            + self.title * title
+            + self.heading * heading
+            + self.emphasis * emphasis
+            + self.anchor * anchor
+    }
+}
+
+// One component of a linear combination
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct WeightedRanking {
+    pub ranking: Ranking,
+    pub weight: f64,
+}
+
+// Corpus statistics shared by every scorer, loaded once when the search engine starts
+pub struct ScoringContext {
+    pub document_count: DocId,
+    pub average_document_length: f64,
+    pub document_lengths: Arc<DocumentLengths>,
+    // length of each document's tf-idf vector, computed by the merger
+    pub document_norms: Arc<DocumentWeights>,
+    pub field_weights: FieldWeights,
+}
+
+impl ScoringContext {
+    // document length divided by the average document length
+    pub fn relative_length(&self, doc_id: DocId) -> f64 {
+        self.relative(self.document_lengths.get(doc_id))
+    }
+
+    pub fn relative(&self, length: u32) -> f64 {
+        if self.average_document_length == 0.0 {
+            return 1.0;
+        }
+        length as f64 / self.average_document_length
+    }
+
+    pub fn weighted_freq(&self, term: &TermMatch) -> f64 {
+        self.field_weights
+            .weighted_freq(term.term_freq, &term.fields)
+    }
+
+    // weighted_freq of term bounds that of any match with no larger frequencies, unless a field
+    // weighs negatively
+    pub fn max_weighted_freq(&self, term: &TermMatch) -> Option<f64> {
+        let weights = self.field_weights;
+        [
+            weights.title,
+            weights.heading,
+            weights.emphasis,
+            weights.anchor,
+        ]
+        .iter()
+        .all(|&weight| weight >= 0.0)
+        .then(|| self.weighted_freq(term))
+    }
+}
+
+// A query term found in a document
+#[derive(Debug, Clone, Copy)]
+pub struct TermMatch {
+    // number of documents containing the term
+    pub document_frequency: u32,
+    pub term_freq: TermFreq,
+    pub fields: FieldFreqs,
+    // how many times the term was repeated in the query
+    pub query_term_freq: u32,
+}
+
+pub trait Scorer: Send + Sync {
+    // contribution of a single query term to a document's score
+    fn score_term(&self, context: &ScoringContext, doc_id: DocId, term: &TermMatch) -> f64;
+
+    // final score of a document from every query term it matched, sums the term scores by default
+    fn score_document(&self, context: &ScoringContext, doc_id: DocId, terms: &[TermMatch]) -> f64 {
+        terms
+            .iter()
+            .map(|term| self.score_term(context, doc_id, term))
+            .sum()
+    }
+
+    // Upper bound of score_term over every document whose term and field frequencies are at
+    // most those of `term` and whose length is at least min_length, never negative. None when
+    // the scorer can't bound a term on its own, which turns off dynamic pruning, as does a
+    // score_document that isn't a sum of score_term
+    fn max_term_score(
+        &self,
+        _context: &ScoringContext,
+        _term: &TermMatch,
+        _min_length: u32,
+    ) -> Option<f64> {
+        None
+    }
+}
+
+pub struct TfIdfScorer;
+
+impl Scorer for TfIdfScorer {
+    fn score_term(&self, context: &ScoringContext, _doc_id: DocId, term: &TermMatch) -> f64 {
+        term.query_term_freq as f64
+            * scoring_tf_idf(
+                context.weighted_freq(term),
+                term.document_frequency,
+                context.document_count,
+            )
+    }
+
+    // tf-idf only grows with the weighted frequency
+    fn max_term_score(
+        &self,
+        context: &ScoringContext,
+        term: &TermMatch,
+        _min_length: u32,
+    ) -> Option<f64> {
+        let term_freq = context.max_weighted_freq(term)?;
+        let score = scoring_tf_idf(term_freq, term.document_frequency, context.document_count);
+        Some(term.query_term_freq as f64 * score.max(0.0))
+    }
+}
+
+// Cosine between the query and document tf-idf vectors. The query norm is the same for every
+// document so it is left out, which keeps the ranking identical. Dividing by the document norm
+// leaves no bound per term
+pub struct CosineScorer;
+
+impl Scorer for CosineScorer {
+    fn score_term(&self, context: &ScoringContext, _doc_id: DocId, term: &TermMatch) -> f64 {
+        let query_weight = scoring_tf_idf(
+            term.query_term_freq as f64,
+            term.document_frequency,
+            context.document_count,
+        );
+        let document_weight = scoring_tf_idf(
+            context.weighted_freq(term),
+            term.document_frequency,
+            context.document_count,
+        );
+        query_weight * document_weight
+    }
+
+    fn score_document(&self, context: &ScoringContext, doc_id: DocId, terms: &[TermMatch]) -> f64 {
+        let dot_product: f64 = terms
+            .iter()
+            .map(|term| self.score_term(context, doc_id, term))
+            .sum();
+        let document_norm = context.document_norms.get(doc_id);
+        if document_norm == 0.0 {
+            0.0
+        } else {
+            dot_product / document_norm
+        }
+    }
+}
+
+pub struct Bm25Scorer {
+    pub params: Bm25Params,
+}
+
+impl Scorer for Bm25Scorer {
+    fn score_term(&self, context: &ScoringContext, doc_id: DocId, term: &TermMatch) -> f64 {
+        term.query_term_freq as f64
+            * scoring_bm25(
+                context.weighted_freq(term),
+                context.relative_length(doc_id),
+                term.document_frequency,
+                context.document_count,
+                self.params,
+            )
+    }
+
+    // BM25 grows with the weighted frequency and shrinks with the document length as long as b is
+    // between 0 and 1, so the bound is the score in the shortest document
+    fn max_term_score(
+        &self,
+        context: &ScoringContext,
+        term: &TermMatch,
+        min_length: u32,
+    ) -> Option<f64> {
+        if self.params.k1 <= 0.0 || !(0.0..=1.0).contains(&self.params.b) {
+            return None;
+        }
+        let score = scoring_bm25(
+            context.max_weighted_freq(term)?,
+            context.relative(min_length),
+            term.document_frequency,
+            context.document_count,
+            self.params,
+        );
+        Some(term.query_term_freq as f64 * score.max(0.0))
+    }
+}
+
+pub struct LinearCombination {
+    pub components: Vec<(f64, Box<dyn Scorer>)>,
+}
+
+impl Scorer for LinearCombination {
+    fn score_term(&self, context: &ScoringContext, doc_id: DocId, term: &TermMatch) -> f64 {
+        self.components
+            .iter()
+            .map(|(weight, scorer)| weight * scorer.score_term(context, doc_id, term))
+            .sum()
+    }
+
+    // combine whole document scores so each component keeps its own normalization
+    fn score_document(&self, context: &ScoringContext, doc_id: DocId, terms: &[TermMatch]) -> f64 {
+        self.components
+            .iter()
+            .map(|(weight, scorer)| weight * scorer.score_document(context, doc_id, terms))
+            .sum()
+    }
+
+    // a negative weight would need a lower bound of its component instead
+    fn max_term_score(
+        &self,
+        context: &ScoringContext,
+        term: &TermMatch,
+        min_length: u32,
+    ) -> Option<f64> {
+        self.components
+            .iter()
+            .map(|(weight, scorer)| {
+                (*weight >= 0.0)
+                    .then(|| scorer.max_term_score(context, term, min_length))
+                    .flatten()
+                    .map(|bound| weight * bound)
+            })
+            .sum()
+    }
+}
+
+// `linear` is only used for Ranking::Linear, a nested linear component is ignored
+pub fn build_scorer(
+    ranking: Ranking,
+    linear: &[WeightedRanking],
+    config: &IndexConfig,
+) -> Box<dyn Scorer> {
+    match ranking {
+        Ranking::TfIdf => Box::new(TfIdfScorer),
+        Ranking::Cosine => Box::new(CosineScorer),
+        Ranking::Bm25 => Box::new(Bm25Scorer {
+            params: config.bm25,
+        }),
+        Ranking::Linear => Box::new(LinearCombination {
+            components: linear
+                .iter()
+                .filter(|component| component.ranking != Ranking::Linear)
+                .map(|component| {
+                    (
+                        component.weight,
+                        build_scorer(component.ranking, &[], config),
+                    )
+                })
+                .collect(),
+        }),
+    }
+}
+
+// term_freq is a float for the same reason as in scoring_bm25, a term with no weight scores 0
+pub fn scoring_tf_idf(term_freq: f64, posting_length: u32, document_count: DocId) -> f64 {
+    if term_freq <= 0.0 {
+        return 0.0;
+    }
+    let tf: f64 = f64::log10(term_freq) + 1.0;
+    let idf: f64 = f64::log10(document_count as f64 / posting_length as f64);
+    tf * idf
+}
+
+// term_freq is a float so a field-weighted pseudo-frequency (BM25F) can be passed in directly,
+// relative_length is the document length divided by the average document length
+pub fn scoring_bm25(
+    term_freq: f64,
+    relative_length: f64,
+    posting_length: u32,
+    document_count: DocId,
+    params: Bm25Params,
+) -> f64 {
+    let document_frequency = posting_length as f64;
+    let idf = f64::ln(
+        (document_count as f64 - document_frequency + 0.5) / (document_frequency + 0.5) + 1.0,
+    );
+    let normalization = params.k1 * (1.0 - params.b + params.b * relative_length);
+    idf * term_freq * (params.k1 + 1.0) / (term_freq + normalization)
+}
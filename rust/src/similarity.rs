@@ -0,0 +1,240 @@
+use crate::config::IndexConfig;
+use crate::single_posting::DocId;
+use std::collections::{HashMap, HashSet};
+
+// Same settings as python/src/similarity.py
+const WORD_NGRAM_SIZE: usize = 2;
+// Pages whose fingerprints differ in at most this many bits are near duplicates. The fingerprint is
+// split into SIMHASH_BANDS bands, two fingerprints this close share at least one band exactly
+// (pigeonhole), so only pages sharing a band have to be compared
+const SIMHASH_MAX_DISTANCE: u32 = 3;
+const SIMHASH_BANDS: usize = 4;
+const BAND_BITS: usize = 64 / SIMHASH_BANDS;
+
+// 64-bit FNV-1a, stable across runs and platforms unlike the std hasher
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// lowercase with every run of whitespace collapsed, so formatting changes don't matter
+pub fn clean_text(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// Each bit of the fingerprint is the majority vote of that bit over the hashes of the page's
+// word bigrams. None when the page has fewer than two words
+pub fn simhash(clean_text: &str) -> Option<u64> {
+    let words: Vec<&str> = clean_text.split(' ').collect();
+    if words.len() < WORD_NGRAM_SIZE {
+        return None;
+    }
+    let ngrams: HashSet<String> = words
+        .windows(WORD_NGRAM_SIZE)
+        .map(|window| window.join(" "))
+        .collect();
+    let mut vector = [0i32; 64];
+    for ngram in ngrams {
+        let hash = fnv1a(ngram.as_bytes());
+        for (bit, count) in vector.iter_mut().enumerate() {
+            *count += if (hash >> bit) & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    Some(
+        vector
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .fold(0, |fingerprint, (bit, _)| fingerprint | 1 << bit),
+    )
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// Detects duplicate pages while indexing, shared by the file workers
+#[derive(Default)]
+pub struct DuplicateDetector {
+    seen_checksums: HashSet<u64>,
+    // band value -> (doc id, fingerprint) of the pages with that band
+    bands: Vec<HashMap<u64, Vec<(DocId, u64)>>>,
+    // doc id -> the first page of its cluster, only for near duplicates
+    representatives: HashMap<DocId, DocId>,
+    pub exact_count: usize,
+    pub near_count: usize,
+}
+
+impl DuplicateDetector {
+    pub fn new() -> Self {
+        Self {
+            bands: vec![HashMap::new(); SIMHASH_BANDS],
+            ..Self::default()
+        }
+    }
+
+    pub fn is_exact_duplicate(&mut self, clean_text: &str) -> bool {
+        let duplicate = !self.seen_checksums.insert(fnv1a(clean_text.as_bytes()));
+        if duplicate {
+            self.exact_count += 1;
+        }
+        duplicate
+    }
+
+    // records an indexed page's fingerprint and joins it to the cluster of the first near duplicate
+    pub fn insert(&mut self, doc_id: DocId, fingerprint: u64) {
+        let bands = band_values(fingerprint);
+        'bands: for (table, band) in self.bands.iter().zip(bands) {
+            for &(other, other_fingerprint) in table.get(&band).into_iter().flatten() {
+                if hamming_distance(fingerprint, other_fingerprint) <= SIMHASH_MAX_DISTANCE {
+                    let representative = self.cluster(other);
+                    self.representatives.insert(doc_id, representative);
+                    self.near_count += 1;
+                    break 'bands;
+                }
+            }
+        }
+        for (table, band) in self.bands.iter_mut().zip(bands) {
+            table.entry(band).or_default().push((doc_id, fingerprint));
+        }
+    }
+
+    pub fn cluster(&self, doc_id: DocId) -> DocId {
+        self.representatives.get(&doc_id).copied().unwrap_or(doc_id)
+    }
+}
+
+fn band_values(fingerprint: u64) -> [u64; SIMHASH_BANDS] {
+    let mut bands = [0; SIMHASH_BANDS];
+    for (index, band) in bands.iter_mut().enumerate() {
+        *band = (fingerprint >> (index * BAND_BITS)) & ((1 << BAND_BITS) - 1);
+    }
+    bands
+}
+
+// Near-duplicate cluster of every document, as the doc id of the cluster's first page (the
+// document itself when it has no duplicates). Stored like DocumentLengths
+pub struct DuplicateClusters {
+    representatives: Vec<DocId>,
+}
+
+impl DuplicateClusters {
+    pub fn new(representatives: Vec<DocId>) -> Self {
+        Self { representatives }
+    }
+
+    pub fn cluster(&self, id: DocId) -> DocId {
+        self.representatives
+            .get((id as usize).wrapping_sub(1))
+            .copied()
+            .unwrap_or(id)
+    }
+
+    pub fn load(config: &IndexConfig) -> std::io::Result<Self> {
+        let bytes = std::fs::read(config.duplicate_clusters_path())?;
+        let representatives = bytes
+            .chunks_exact(4)
+            .map(|chunk| DocId::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        Ok(Self { representatives })
+    }
+
+    pub fn save(&self, config: &IndexConfig) -> std::io::Result<()> {
+        let bytes: Vec<u8> = self
+            .representatives
+            .iter()
+            .flat_map(|id| id.to_le_bytes())
+            .collect();
+        std::fs::write(config.duplicate_clusters_path(), bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: u64 = 0x0123_4567_89ab_cdef;
+
+    // flips the given number of low bits of the fingerprint, all inside its first band
+    fn flipped(bits: u32) -> u64 {
+        FINGERPRINT ^ ((1 << bits) - 1)
+    }
+
+    #[test]
+    fn a_page_within_the_distance_joins_the_cluster() {
+        let mut detector = DuplicateDetector::new();
+        detector.insert(1, FINGERPRINT);
+        detector.insert(2, flipped(SIMHASH_MAX_DISTANCE));
+        assert_eq!(
+            hamming_distance(FINGERPRINT, flipped(SIMHASH_MAX_DISTANCE)),
+            3
+        );
+        assert_eq!(detector.cluster(1), 1);
+        assert_eq!(detector.cluster(2), 1);
+        assert_eq!(detector.near_count, 1);
+    }
+
+    #[test]
+    fn a_page_past_the_distance_starts_its_own_cluster() {
+        let mut detector = DuplicateDetector::new();
+        detector.insert(1, FINGERPRINT);
+        // shares three of the four bands, but is one bit too far
+        detector.insert(2, flipped(SIMHASH_MAX_DISTANCE + 1));
+        assert_eq!(detector.cluster(2), 2);
+        assert_eq!(detector.near_count, 0);
+    }
+
+    #[test]
+    fn a_duplicate_of_a_duplicate_joins_the_first_page() {
+        let mut detector = DuplicateDetector::new();
+        detector.insert(1, FINGERPRINT);
+        detector.insert(2, flipped(2));
+        // 4 bits from page 1, 2 bits from page 2
+        detector.insert(3, flipped(2) ^ 0b1100);
+        assert_eq!(detector.cluster(3), 1);
+    }
+
+    #[test]
+    fn exact_duplicates_ignore_formatting() {
+        let mut detector = DuplicateDetector::new();
+        assert!(!detector.is_exact_duplicate(&clean_text("Hello  World")));
+        assert!(detector.is_exact_duplicate(&clean_text("hello\n\tworld ")));
+        assert_eq!(detector.exact_count, 1);
+        assert_eq!(simhash(&clean_text("hello")), None);
+        assert_eq!(
+            simhash(&clean_text("Hello  World")),
+            simhash(&clean_text("hello world"))
+        );
+    }
+
+    #[test]
+    fn duplicate_clusters_round_trip() {
+        let index_dir =
+            std::env::temp_dir().join(format!("similarity_clusters_{}", std::process::id()));
+        std::fs::create_dir_all(&index_dir).unwrap();
+        let config = IndexConfig {
+            index_dir: index_dir.clone(),
+            ..IndexConfig::default()
+        };
+        DuplicateClusters::new(vec![1, 1, 3, 1])
+            .save(&config)
+            .unwrap();
+        let clusters = DuplicateClusters::load(&config);
+        std::fs::remove_dir_all(&index_dir).unwrap();
+        let clusters = clusters.unwrap();
+        assert_eq!(
+            (1..=4).map(|id| clusters.cluster(id)).collect::<Vec<_>>(),
+            vec![1, 1, 3, 1]
+        );
+        // ids outside the file are their own cluster
+        assert_eq!(clusters.cluster(0), 0);
+        assert_eq!(clusters.cluster(5), 5);
+    }
+}
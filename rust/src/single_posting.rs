@@ -1,7 +1,32 @@
+// u32 so a crawl can go past 65,535 pages, and long pages don't overflow their term frequency
+pub type DocId = u32;
+pub type TermFreq = u32;
+
+// Parts of a page whose terms count for more than body text, weighted at query time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Title,
+    // h1 to h6
+    Heading,
+    // strong and b
+    Emphasis,
+    // text of the links pointing at the page
+    Anchor,
+}
+
+pub const FIELD_COUNT: usize = 4;
+
+// occurrences of a term in each field, indexed by `Field as usize`
+pub type FieldFreqs = [TermFreq; FIELD_COUNT];
+
 #[derive(Debug, Clone)]
 pub struct Posting {
-    pub doc_id: u16,
-    pub term_freq: u16,
+    pub doc_id: DocId,
+    // occurrences in the body text, title and anchor text only count in `fields`
+    pub term_freq: TermFreq,
+    // token offsets of the term within the body text, ascending
+    pub positions: Vec<u32>,
+    pub fields: FieldFreqs,
 }
 
 impl Ord for Posting {
@@ -28,11 +53,31 @@ impl PartialEq for Posting {
 
 #[allow(dead_code)]
 impl Posting {
-    pub fn new(doc_id: u16, term_freq: u16) -> Posting {
-        Posting { doc_id, term_freq }
+    pub fn new(doc_id: DocId, term_freq: TermFreq, positions: Vec<u32>) -> Posting {
+        Posting {
+            doc_id,
+            term_freq,
+            positions,
+            fields: [0; FIELD_COUNT],
+        }
+    }
+
+    pub fn add_field_occurrence(&mut self, field: Field) {
+        self.fields[field as usize] += 1;
+    }
+
+    // adds the occurrences of the same document from another batch
+    pub fn combine(&mut self, other: &Posting) {
+        self.term_freq += other.term_freq;
+        self.positions.extend_from_slice(&other.positions);
+        self.positions.sort_unstable();
+        for (field, other_field) in self.fields.iter_mut().zip(other.fields) {
+            *field += other_field;
+        }
     }
 
-    pub fn increment_freq(&mut self) {
+    pub fn add_position(&mut self, position: u32) {
         self.term_freq += 1;
+        self.positions.push(position);
     }
 }
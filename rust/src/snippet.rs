@@ -0,0 +1,195 @@
+use crate::tokenizer::Tokenizer;
+use std::collections::HashSet;
+use std::ops::Range;
+
+// words shown per snippet
+const SNIPPET_WORDS: usize = 30;
+// words shown before the first query term of a snippet
+const SNIPPET_LEAD: usize = 5;
+const MAX_SNIPPETS: usize = 2;
+
+// Up to MAX_SNIPPETS short passages of text around the query terms (already tokenized), with
+// every query term wrapped in <mark></mark> and the rest HTML-escaped. The windows holding the
+// most distinct query terms are picked first. Falls back to the start of the text when no query
+// term appears in it, e.g. when the page only matched through its title or anchor text
+pub fn make_snippets(text: &str, query_terms: &[String], tokenizer: &Tokenizer) -> Vec<String> {
+    let tokens = tokenizer.tokenize_with_offsets(text);
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    let is_match: Vec<bool> = tokens
+        .iter()
+        .map(|(_, token)| query_terms.contains(token))
+        .collect();
+
+    // candidate windows start a few words before each match
+    let mut windows: Vec<(usize, Range<usize>)> = is_match
+        .iter()
+        .enumerate()
+        .filter(|(_, &matched)| matched)
+        .map(|(index, _)| {
+            let start = index.saturating_sub(SNIPPET_LEAD);
+            let window = start..(start + SNIPPET_WORDS).min(tokens.len());
+            let distinct: HashSet<&String> = tokens[window.clone()]
+                .iter()
+                .filter(|(_, token)| query_terms.contains(token))
+                .map(|(_, token)| token)
+                .collect();
+            (distinct.len(), window)
+        })
+        .collect();
+    // most distinct terms first, earlier in the page on ties
+    windows.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.start.cmp(&b.1.start)));
+
+    let mut chosen: Vec<Range<usize>> = Vec::new();
+    for (_, window) in windows {
+        if chosen.len() == MAX_SNIPPETS {
+            break;
+        }
+        if chosen
+            .iter()
+            .all(|other| window.end <= other.start || other.end <= window.start)
+        {
+            chosen.push(window);
+        }
+    }
+    if chosen.is_empty() {
+        chosen.push(0..SNIPPET_WORDS.min(tokens.len()));
+    }
+    // shown in page order
+    chosen.sort_by_key(|window| window.start);
+
+    chosen
+        .into_iter()
+        .map(|window| render(text, &tokens, &is_match, window))
+        .collect()
+}
+
+fn render(
+    text: &str,
+    tokens: &[(Range<usize>, String)],
+    is_match: &[bool],
+    window: Range<usize>,
+) -> String {
+    let mut snippet = String::new();
+    if window.start > 0 {
+        snippet.push_str("... ");
+    }
+    let mut previous_end = tokens[window.start].0.start;
+    for index in window.clone() {
+        let (range, _) = &tokens[index];
+        // keep the punctuation between words but not the line breaks and indentation
+        snippet.push_str(&escape_html(&collapse_whitespace(
+            &text[previous_end..range.start],
+        )));
+        let word = escape_html(&text[range.clone()]);
+        if is_match[index] {
+            snippet.push_str("<mark>");
+            snippet.push_str(&word);
+            snippet.push_str("</mark>");
+        } else {
+            snippet.push_str(&word);
+        }
+        previous_end = range.end;
+    }
+    if window.end < tokens.len() {
+        snippet.push_str(" ...");
+    }
+    snippet
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for character in text.chars() {
+        if character.is_whitespace() {
+            if !in_whitespace {
+                collapsed.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            collapsed.push(character);
+            in_whitespace = false;
+        }
+    }
+    collapsed
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippets(text: &str, query: &str) -> Vec<String> {
+        let tokenizer = Tokenizer::new();
+        make_snippets(text, &tokenizer.tokenize(query), &tokenizer)
+    }
+
+    // "w0 w1 ... w{count - 1}"
+    fn words(count: usize) -> Vec<String> {
+        (0..count).map(|index| format!("w{index}")).collect()
+    }
+
+    #[test]
+    fn the_window_with_the_most_distinct_terms_is_picked() {
+        let mut text = words(100);
+        text[10] = "cat".to_string();
+        text[60] = "cat".to_string();
+        text[63] = "dog".to_string();
+        let result = snippets(&text.join(" "), "cat dog");
+        // the window around both terms first, then the lone cat, in page order
+        assert_eq!(result.len(), 2);
+        assert!(result[0].starts_with("... w5 w6 w7 w8 w9 <mark>cat</mark> w11"));
+        assert!(result[1]
+            .starts_with("... w55 w56 w57 w58 w59 <mark>cat</mark> w61 w62 <mark>dog</mark>"));
+        assert!(result[1].ends_with("w84 ..."));
+    }
+
+    #[test]
+    fn overlapping_windows_are_shown_once() {
+        let mut text = words(100);
+        text[40] = "cat".to_string();
+        text[45] = "cat".to_string();
+        let result = snippets(&text.join(" "), "cat");
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].matches("<mark>").count(), 2);
+    }
+
+    #[test]
+    fn terms_at_the_edges_of_the_body_have_no_ellipsis() {
+        let result = snippets("cat sat on the mat with the dog", "cat dog");
+        assert_eq!(
+            result,
+            vec!["<mark>cat</mark> sat on the mat with the <mark>dog</mark>"]
+        );
+        let mut text = words(50);
+        text[49] = "dog".to_string();
+        let result = snippets(&text.join(" "), "dog");
+        assert_eq!(result.len(), 1);
+        assert!(result[0].starts_with("... w44 "));
+        assert!(result[0].ends_with("w48 <mark>dog</mark>"));
+    }
+
+    #[test]
+    fn text_around_the_marks_is_escaped() {
+        let result = snippets("if a<b && \"cat\" > dog then <script>", "cat");
+        assert_eq!(
+            result,
+            vec!["if a&lt;b &amp;&amp; &quot;<mark>cat</mark>&quot; &gt; dog then &lt;script"]
+        );
+    }
+
+    #[test]
+    fn the_start_of_the_text_is_shown_without_matches() {
+        let text = words(40).join("\n  ");
+        let result = snippets(&text, "cat");
+        assert_eq!(result, vec![format!("{} ...", words(30).join(" "))]);
+        assert!(snippets("", "cat").is_empty());
+    }
+}
@@ -1,13 +1,33 @@
 use porter_stemmer::stem;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+// anything that isn't alphanumeric separates tokens
+const SPLIT_PATTERN: &str = r"[^a-zA-Z0-9]+";
+
 pub struct Tokenizer {}
 
+// Recorded in the index metadata so a query is tokenized the same way the index was
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenizerSettings {
+    pub split_pattern: String,
+    pub lowercase: bool,
+    pub stemmer: String,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Tokenizer {
     pub fn new() -> Tokenizer {
         Tokenizer {}
     }
     pub fn tokenize(&self, token: &str) -> Vec<String> {
-        let pattern = Regex::new(r"[^a-zA-Z0-9]+").unwrap();
+        let pattern = Regex::new(SPLIT_PATTERN).unwrap();
         pattern
             .split(token)
             .map(|s| s.to_lowercase())
@@ -16,7 +36,35 @@ impl Tokenizer {
             .collect::<Vec<String>>()
     }
 
+    // same tokens as tokenize, each with the byte range of the word it came from
+    pub fn tokenize_with_offsets(&self, text: &str) -> Vec<(Range<usize>, String)> {
+        let pattern = Regex::new(SPLIT_PATTERN).unwrap();
+        let mut tokens = Vec::new();
+        let mut start = 0;
+        let separators = pattern
+            .find_iter(text)
+            .map(|separator| separator.range())
+            .chain(std::iter::once(text.len()..text.len()));
+        for separator in separators {
+            let word = &text[start..separator.start];
+            let token = self.porter_stemmer(&word.to_lowercase());
+            if !token.is_empty() {
+                tokens.push((start..separator.start, token));
+            }
+            start = separator.end;
+        }
+        tokens
+    }
+
     pub fn porter_stemmer(&self, token: &str) -> String {
         stem(token)
     }
+
+    pub fn settings(&self) -> TokenizerSettings {
+        TokenizerSettings {
+            split_pattern: SPLIT_PATTERN.to_string(),
+            lowercase: true,
+            stemmer: String::from("porter"),
+        }
+    }
 }